@@ -1,5 +1,3 @@
-local utf = require("utf8")
-
 local types = {
     lua = { sy = "", cl = { 0x99, 0x99, 0xff } },
     sh = { sy = "󱆃", cl = { 0x99, 0xff, 0x99 } },
@@ -31,11 +29,10 @@ local function parse_env(var)
     return result
 end
 
-local data_dirs = parse_env(bash("echo $XDG_DATA_DIRS")[1])
-local conf_dirs = parse_env(bash("echo $XDG_CONFIG_DIRS")[1])
+local data_dirs = parse_env(os.getenv("XDG_DATA_DIRS") or "")
+local conf_dirs = parse_env(os.getenv("XDG_CONFIG_DIRS") or "")
 
-local home = bash("echo $HOME")[1]
-home = string.sub(home, 1, string.len(home) - 1)
+local home = os.getenv("HOME") or ""
 local special_path = {
     [home] = { sy = "󱂵" },
     [home .. "/Documents"] = { sy = "󱧶" },
@@ -50,103 +47,86 @@ for _, dir in ipairs(conf_dirs) do
     special_path[dir] = { sy = "" }
 end
 
-local function into_cells(s, col)
-    if not col then
-        col = "white"
+-- a symbol followed by the name, each span in its own color
+local function icon(sy, sy_col, text, col)
+    return {
+        { sy, fg = sy_col or "white" },
+        { " " .. text, fg = col or "white" },
+    }
+end
+
+local function register_generic(sy, cl, text_cl)
+    return function(name, _, _)
+        return icon(sy, cl, name, text_cl)
     end
-    local t = {}
-    for i = 1, utf.len(s), 1 do
-        local s_beg = utf8.offset(s, i)
-        local s_end = utf8.offset(s, i + 1)
-        local c = ""
+end
 
-        if s_end then
-            c = string.sub(s, s_beg, s_end - 1)
-        else
-            c = string.sub(s, s_beg)
+-- git repositories are marked whatever else the directory is
+local function register_dir(sy, cl, text_cl)
+    return function(name, path, _)
+        if fs.exists(path .. "/.git") then
+            return icon("", { 0x77, 0x77, 0xff }, name .. ":")
         end
-
-        table.insert(t, {
-            chr = c,
-            col = col,
-        })
+        return icon(sy, cl or { 0x77, 0x77, 0xff }, name .. "/", text_cl)
     end
+end
 
-    return t
+local rules = {
+    {
+        name = ".gitignore",
+        type = "file",
+        format = register_generic("", { 0xaB, 0x52, 0x31 }, { 0x99, 0x99, 0x99 }),
+    },
+    {
+        regex = "^make(file)?$",
+        ignore_case = true,
+        type = "file",
+        format = register_generic("", { 0xaa, 0x33, 0x11 }),
+    },
+    {
+        name = ".git",
+        type = "dir",
+        format = register_dir("", { 0xaB, 0x52, 0x31 }, { 0x99, 0x99, 0x99 }),
+    },
+}
+
+for path, sp in pairs(special_path) do
+    table.insert(rules, { path = path, type = "dir", format = register_dir(sp.sy, sp.col) })
 end
 
+local hidden_dir = register_dir("", { 0x55, 0x55, 0x99 }, { 0x99, 0x99, 0x99 })
+local dir = register_dir("")
+
 local formats = {
     file = {
         function(name, _, _)
-            local fmt = {}
-            if name == ".gitignore" then
-                fmt = into_cells(" " .. name, { 0x99, 0x99, 0x99 })
-                fmt[1].col = { 0xaB, 0x52, 0x31 }
-            elseif string.lower(name) == "make" or string.lower(name) == "makefile" then
-                fmt = into_cells(" " .. name)
-                fmt[1].col = { 0xaa, 0x33, 0x11 }
-            elseif string.sub(name, 1, 1) == "." then
-                local str = "󰈔 " .. name
-                fmt = into_cells(str, { 0xaa, 0xaa, 0xaa })
-            else
-                local str = "󰈔 " .. name
-                fmt = into_cells(str)
+            if string.sub(name, 1, 1) == "." then
+                local col = { 0xaa, 0xaa, 0xaa }
+                return icon("󰈔", col, name, col)
             end
-            return fmt
+            return icon("󰈔", nil, name)
         end,
     },
     dirs = {
-        function(name, path, _)
-            local out = bash("ls -a " .. path .. " | grep git")
-            if out[2] == 0 then
-                local fmt = into_cells(" " .. name .. ":")
-                fmt[1].col = { 0x77, 0x77, 0xff }
-                return fmt
-            end
-
-            local sp = special_path[path]
-            if sp ~= nil then
-                local fmt = into_cells(sp.sy .. " " .. name .. "/")
-                if sp.col then
-                    fmt[1].col = sp.col
-                else
-                    fmt[1].col = { 0x77, 0x77, 0xff }
-                end
-                return fmt
-            end
-
-            if name == ".git" then
-                local fmt = into_cells(" " .. name .. "/", { 0x99, 0x99, 0x99 })
-                fmt[1].col = { 0xaB, 0x52, 0x31 }
-                return fmt
-            end
-
-            -- default
-            local str = " " .. name .. "/"
-            local fmt = {}
+        function(name, path, tick)
             if string.sub(name, 1, 1) == "." then
-                fmt = into_cells(str, { 0x99, 0x99, 0x99 })
-                fmt[1].col = { 0x55, 0x55, 0x99 }
-            else
-                fmt = into_cells(str)
-                fmt[1].col = { 0x77, 0x77, 0xff }
+                return hidden_dir(name, path, tick)
             end
-
-            return fmt
+            return dir(name, path, tick)
+        end,
+    },
+    links = {
+        -- links to files borrow the look of what they point to
+        function(name, path, _, meta)
+            if not meta.broken and not fs.is_dir(path) then
+                return { meta:format_target(name), "@" }
+            end
+            return icon("", nil, name .. "@")
         end,
     },
+    rules = rules,
 }
 
-local function register_generic(sy, cl)
-    return function(name, _, _)
-        local cells = into_cells(sy .. " " .. name)
-        if cl then
-            cells[1].col = cl
-        end
-        return cells
-    end
-end
-
 for type, sym in pairs(types) do
     local cl = sym.cl
     local sy = sym.sy
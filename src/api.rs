@@ -1,15 +1,40 @@
-use std::{
-    io::Read,
-    process::{Command, Stdio},
-};
+use std::sync::atomic::Ordering;
 
-use crate::{LUA, MAP, util::FileType};
-use mlua::{Function, Lua, Table};
+use crate::{
+    LUA, MAP,
+    explorer::COLUMNS,
+    fmt::{EXTENSIONS_IGNORE_CASE, MIME_ENABLED},
+    lua_process::{Request, run},
+    preview::PREVIEWS,
+    rules::{Rule, add_rules},
+    util::FileType,
+};
+use mlua::{Function, Lua, Table, Value};
 
 pub fn get_formats(_: &Lua, tb: mlua::Table) -> mlua::Result<()> {
     let mut map = MAP
         .lock()
         .map_err(|err| mlua::Error::RuntimeError(err.to_string()))?;
+    // extensions already registered, eg: from LS_COLORS, are moved to lowercase
+    // too, an existing lowercase one wins
+    let ignore_case = tb.get::<Option<bool>>("ignore_case")?.unwrap_or(false);
+    if ignore_case {
+        EXTENSIONS_IGNORE_CASE.store(true, Ordering::Relaxed);
+        let mixed: Vec<String> = map
+            .keys()
+            .filter_map(|ft| match ft {
+                FileType::OtherFile(ext) if ext.to_lowercase() != *ext => Some(ext.clone()),
+                _ => None,
+            })
+            .collect();
+        for ext in mixed {
+            if let Some(f) = map.remove(&FileType::OtherFile(ext.clone())) {
+                map.entry(FileType::OtherFile(ext.to_lowercase()))
+                    .or_insert(f);
+            }
+        }
+    }
+
     let file_formats: Table = tb.get("file")?;
 
     match file_formats.get(1) {
@@ -18,8 +43,10 @@ pub fn get_formats(_: &Lua, tb: mlua::Table) -> mlua::Result<()> {
         }
         Err(_) => {}
     }
+    // keys may be compound extensions, eg: `tar.gz`
     for kv in file_formats.pairs() {
         let (k, v): (String, Function) = kv?;
+        let k = if ignore_case { k.to_lowercase() } else { k };
         map.insert(FileType::OtherFile(k), v);
     }
 
@@ -30,43 +57,146 @@ pub fn get_formats(_: &Lua, tb: mlua::Table) -> mlua::Result<()> {
         }
         Err(_) => {}
     }
-    for kv in file_formats.pairs() {
+    for kv in dirs_format.pairs() {
         let (k, v): (String, Function) = kv?;
         map.insert(FileType::OtherDir(k), v);
     }
 
+    // like files and dirs with the default at [1], or just that function
+    match tb.get::<Value>("links")? {
+        Value::Function(default) => {
+            map.insert(FileType::GenericSymLink, default);
+        }
+        Value::Table(links) => {
+            if let Some(default) = links.get::<Option<Function>>(1)? {
+                map.insert(FileType::GenericSymLink, default);
+            }
+        }
+        _ => {}
+    }
+
+    // keyed on the type found from the content, files are only read for it
+    // once one of these is registered
+    if let Some(mime_formats) = tb.get::<Option<Table>>("mime")? {
+        for kv in mime_formats.pairs() {
+            let (k, v): (String, Function) = kv?;
+            map.insert(FileType::Mime(k), v);
+            MIME_ENABLED.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // types that can only be styled as a whole take a single function
+    let generics = [
+        ("exec", FileType::GenericExecutable),
+        ("fifo", FileType::GenericFifo),
+        ("socket", FileType::GenericSocket),
+        ("block", FileType::GenericBlockDevice),
+        ("char", FileType::GenericCharDevice),
+    ];
+    for (key, ft) in generics {
+        if let Some(f) = tb.get::<Option<Function>>(key)? {
+            map.insert(ft, f);
+        }
+    }
+
+    // rules go before the extension and directory name tables
+    if let Some(rules) = tb.get::<Option<Table>>("rules")? {
+        let rules = rules
+            .sequence_values::<Table>()
+            .map(|rule| Rule::from_table(&rule?))
+            .collect::<mlua::Result<_>>()?;
+        add_rules(rules);
+    }
+
     Ok(())
 }
 
-pub fn bash(l: &Lua, s: String) -> mlua::Result<Table> {
-    let mut cmd = Command::new("bash");
-    cmd.arg("-c")
-        .arg(s)
-        .stderr(Stdio::piped())
-        .stdout(Stdio::piped());
-    let mut result = cmd.spawn()?;
-    let stdout = match &mut result.stdout {
-        Some(s) => {
-            let mut buf = String::new();
-            s.read_to_string(&mut buf)?;
-            buf
+/// the same tables as `load_formats` but with previewers, only what the
+/// explorer can preview is read
+pub fn get_previews(_: &Lua, tb: mlua::Table) -> mlua::Result<()> {
+    let mut map = PREVIEWS
+        .lock()
+        .map_err(|err| mlua::Error::RuntimeError(err.to_string()))?;
+    let ignore_case = EXTENSIONS_IGNORE_CASE.load(Ordering::Relaxed);
+
+    if let Some(files) = tb.get::<Option<Table>>("file")? {
+        if let Some(default) = files.get::<Option<Function>>(1)? {
+            map.insert(FileType::GenericFile, default);
         }
-        _ => String::new(),
-    };
-    let stderr = match &mut result.stderr {
-        Some(s) => {
-            let mut buf = String::new();
-            s.read_to_string(&mut buf)?;
-            buf
+        for kv in files.pairs() {
+            let (k, v): (String, Function) = kv?;
+            let k = if ignore_case { k.to_lowercase() } else { k };
+            map.insert(FileType::OtherFile(k), v);
         }
-        _ => String::new(),
+    }
+
+    if let Some(dirs) = tb.get::<Option<Table>>("dirs")? {
+        if let Some(default) = dirs.get::<Option<Function>>(1)? {
+            map.insert(FileType::GenericDir, default);
+        }
+        for kv in dirs.pairs() {
+            let (k, v): (String, Function) = kv?;
+            map.insert(FileType::OtherDir(k), v);
+        }
+    }
+
+    if let Some(mime) = tb.get::<Option<Table>>("mime")? {
+        for kv in mime.pairs() {
+            let (k, v): (String, Function) = kv?;
+            map.insert(FileType::Mime(k), v);
+        }
+    }
+
+    let generics = [
+        ("exec", FileType::GenericExecutable),
+        ("links", FileType::GenericSymLink),
+        ("fifo", FileType::GenericFifo),
+        ("socket", FileType::GenericSocket),
+        ("block", FileType::GenericBlockDevice),
+        ("char", FileType::GenericCharDevice),
+    ];
+    for (key, ft) in generics {
+        if let Some(f) = tb.get::<Option<Function>>(key)? {
+            map.insert(ft, f);
+        }
+    }
+
+    Ok(())
+}
+
+/// the relative widths of the explorer's columns, eg: `{ 1, 3, 4 }` for the
+/// parent, the listing and the preview
+pub fn get_layout(_: &Lua, tb: mlua::Table) -> mlua::Result<()> {
+    if let Some(columns) = tb.get::<Option<Vec<u32>>>("columns")? {
+        if columns.is_empty() || columns.len() > 3 || columns.contains(&0) {
+            return Err(mlua::Error::RuntimeError(
+                "columns takes one to three widths above zero".to_string(),
+            ));
+        }
+        *COLUMNS.lock().unwrap() = columns;
+    }
+
+    Ok(())
+}
+
+/// kept for the older configs, `process.run` is what new ones should use
+pub fn bash(l: &Lua, s: String) -> mlua::Result<Table> {
+    let req = Request {
+        cmd: "bash".to_string(),
+        args: vec!["-c".to_string(), s],
+        ..Default::default()
     };
+    let out = run(&req)?;
+    // like the shell does, a killed process exits with 128 + the signal
+    let code = out
+        .code
+        .or(out.signal.map(|sig| 128 + sig))
+        .unwrap_or_default();
 
-    let r = result.wait()?.code().unwrap_or(0);
     let k = l.create_table()?;
-    k.set(1, stdout)?;
-    k.set(2, stderr)?;
-    k.set(3, r)?;
+    k.set(1, String::from_utf8_lossy(&out.stdout))?;
+    k.set(2, String::from_utf8_lossy(&out.stderr))?;
+    k.set(3, code)?;
 
-    return Ok(k);
+    Ok(k)
 }
@@ -0,0 +1,82 @@
+use std::{
+    cell::RefCell,
+    io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::quote::{QuotingStyle, quote};
+
+fn name(path: &Path) -> String {
+    quote(path.as_os_str(), QuotingStyle::Literal)
+}
+
+/// a problem with a single entry, the rest of the listing goes on without it
+#[derive(Debug, Error)]
+pub enum EntryError {
+    #[error("cannot access '{}': {source}", name(path))]
+    Access { path: PathBuf, source: io::Error },
+
+    #[error("cannot open directory '{}': {source}", name(path))]
+    OpenDir { path: PathBuf, source: io::Error },
+
+    #[error("reading directory '{}': {source}", name(path))]
+    ReadDir { path: PathBuf, source: io::Error },
+
+    #[error("cannot read symbolic link '{}': {source}", name(path))]
+    ReadLink { path: PathBuf, source: io::Error },
+}
+
+/// a file operation of the explorer that didn't go through
+#[derive(Debug, Error)]
+pub enum OpError {
+    #[error("cannot copy '{}' to '{}': {source}", name(from), name(to))]
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+
+    #[error("cannot move '{}' to '{}': {source}", name(from), name(to))]
+    Move {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+
+    #[error("cannot rename '{}' to '{}': {source}", name(from), name(to))]
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+
+    #[error("cannot remove '{}': {source}", name(path))]
+    Remove { path: PathBuf, source: io::Error },
+
+    #[error("cannot create '{}': {source}", name(path))]
+    Create { path: PathBuf, source: io::Error },
+
+    #[error("'{}' and '{}' are inside one another", name(from), name(to))]
+    Overlap { from: PathBuf, to: PathBuf },
+
+    #[error("invalid file name '{0}'")]
+    InvalidName(String),
+}
+
+thread_local! {
+    /// each thread keeps its own, the explorer's preview reads directories
+    /// besides the listing
+    static ERRORS: RefCell<Vec<EntryError>> = const { RefCell::new(Vec::new()) };
+}
+
+/// stores the error to be shown once the listing is done
+pub fn report(err: EntryError) {
+    ERRORS.with_borrow_mut(|errors| errors.push(err));
+}
+
+/// what was reported on this thread since the last call
+pub fn take_errors() -> Vec<EntryError> {
+    ERRORS.with_borrow_mut(std::mem::take)
+}
@@ -1,92 +1,687 @@
-use std::{cmp::min, env::current_dir, io, path::PathBuf, thread, time::Duration};
+use std::{
+    cmp::min,
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    sync::{
+        LazyLock, Mutex,
+        mpsc::{Receiver, SendError, Sender, TryRecvError},
+    },
+    time::Duration,
+};
 
 use anyhow::Result;
+use clap::Parser;
 use crossterm::{
-    event, execute,
+    event::{self, KeyCode},
+    execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
 use ratatui::{
     Terminal,
     backend::CrosstermBackend,
     buffer::Cell,
-    layout::{Position, Rect},
+    layout::{Constraint, Layout as Split, Position, Rect},
     style,
     widgets::{List, ListState},
 };
+use unicode_width::UnicodeWidthStr;
 
-use crate::util::{Color, Format, read_dir};
+use crate::{
+    error::{EntryError, OpError, take_errors},
+    fileops::{self, OnConflict},
+    get_options,
+    lua_process::clear_cache,
+    preview::{self, Preview, Request},
+    quote::quote,
+    sort::sort_entries,
+    util::{
+        BLINK, BOLD, Color, DIM, Entry, EntryType, Format, ITALIC, REVERSE, ReadOptions,
+        STRIKETHROUGH, Style, UNDERLINE, grapheme_width, read_dir,
+    },
+};
 
-fn set_color(cell: &mut Cell, color: &Color) {
-    cell.set_fg(match color {
+fn to_color(color: &Color) -> style::Color {
+    match color {
+        Color::BLACK => style::Color::Black,
         Color::RED => style::Color::Red,
         Color::GREEN => style::Color::Green,
         Color::YELLOW => style::Color::Yellow,
         Color::BLUE => style::Color::Blue,
         Color::MAGENTA => style::Color::Magenta,
         Color::CYAN => style::Color::Cyan,
+        Color::WHITE => style::Color::Gray,
+        Color::BRIGHT_BLACK => style::Color::DarkGray,
+        Color::BRIGHT_RED => style::Color::LightRed,
+        Color::BRIGHT_GREEN => style::Color::LightGreen,
+        Color::BRIGHT_YELLOW => style::Color::LightYellow,
+        Color::BRIGHT_BLUE => style::Color::LightBlue,
+        Color::BRIGHT_MAGENTA => style::Color::LightMagenta,
+        Color::BRIGHT_CYAN => style::Color::LightCyan,
+        Color::BRIGHT_WHITE => style::Color::White,
+        Color::PALETTE(n) => style::Color::Indexed(*n),
         Color::RGB(r, g, b) => style::Color::Rgb(*r, *g, *b),
-        _ => style::Color::White,
-    });
+    }
+}
+
+fn set_style(cell: &mut Cell, s: &Style) {
+    let mut modifier = style::Modifier::empty();
+    let modifiers = [
+        (BOLD, style::Modifier::BOLD),
+        (DIM, style::Modifier::DIM),
+        (ITALIC, style::Modifier::ITALIC),
+        (UNDERLINE, style::Modifier::UNDERLINED),
+        (BLINK, style::Modifier::SLOW_BLINK),
+        (REVERSE, style::Modifier::REVERSED),
+        (STRIKETHROUGH, style::Modifier::CROSSED_OUT),
+    ];
+    for (attr, m) in modifiers {
+        if s.attrs & attr != 0 {
+            modifier |= m;
+        }
+    }
+
+    let mut rs = style::Style::default()
+        .fg(to_color(&s.fg))
+        .add_modifier(modifier);
+    if let Some(bg) = &s.bg {
+        rs = rs.bg(to_color(bg));
+    }
+    cell.set_style(rs);
 }
 
 impl ratatui::widgets::Widget for &Format {
     fn render(self, area: ratatui::layout::Rect, buf: &mut ratatui::buffer::Buffer) {
-        for i in 0..min(area.width, self.v.len() as u16) {
-            let val = &self.v[i as usize];
-            if let Some(cell) = buf.cell_mut(Position::new(area.x + i, area.y)) {
-                cell.set_char(val.chr);
-                set_color(cell, &self.v[i as usize].col);
+        let mut x = 0;
+        for (g, style) in self.graphemes() {
+            let w = grapheme_width(g) as u16;
+            if x + w > area.width {
+                break;
+            }
+            if let Some(cell) = buf.cell_mut(Position::new(area.x + x, area.y)) {
+                cell.set_symbol(g);
+                set_style(cell, &style);
+            }
+            // the cells hidden by a wide grapheme are cleared
+            for i in 1..w {
+                if let Some(cell) = buf.cell_mut(Position::new(area.x + x + i, area.y)) {
+                    cell.reset();
+                }
             }
+            x += w;
         }
     }
 }
 
+#[derive(Debug, Parser, Clone)]
+pub struct ExplorerArgs {
+    /// rows kept visible above and below the selection while scrolling
+    #[arg(long, default_value_t = 3)]
+    scrolloff: usize,
+}
+
+/// relative widths of the columns, set from `load_layout`. One is the listing
+/// alone, two add the preview and three the parent directory before them
+pub static COLUMNS: LazyLock<Mutex<Vec<u32>>> = LazyLock::new(|| Mutex::new(vec![1, 1]));
+
+/// columns narrower than this are dropped, the parent first
+const MIN_COLUMN: u16 = 16;
+
+/// the entries of a directory, formatted once they are visible
 #[derive(Default)]
+struct Listing {
+    entries: Vec<Entry>,
+    cache: Vec<Option<Format>>,
+    state: ListState,
+}
+
+impl Listing {
+    fn read(dir: &PathBuf) -> Result<Self, EntryError> {
+        let mut entries = read_dir(dir, &ReadOptions::default(), 0)?;
+        sort_entries(&mut entries);
+        Ok(Listing {
+            cache: entries.iter().map(|_| None).collect(),
+            entries,
+            state: ListState::default(),
+        })
+    }
+
+    /// selects the entry at `path`, or the first one when `first` is set
+    fn select_path(&mut self, path: Option<&PathBuf>, first: bool) {
+        let found = path.and_then(|path| self.entries.iter().position(|e| e.path == *path));
+        let selected = match found {
+            Some(i) => Some(i),
+            None if first && !self.entries.is_empty() => Some(0),
+            None => None,
+        };
+        self.state.select(selected);
+    }
+
+    fn selected(&self) -> Option<&Entry> {
+        self.entries.get(self.state.selected()?)
+    }
+
+    fn select(&mut self, i: usize) {
+        if !self.entries.is_empty() {
+            self.state.select(Some(min(i, self.entries.len() - 1)));
+        }
+    }
+
+    /// moves the viewport of `height` rows so the selection stays at least
+    /// `scrolloff` rows away from its edges, then formats what became visible.
+    /// The last formatting error is returned
+    fn scroll(&mut self, height: usize, scrolloff: usize) -> Option<String> {
+        let len = self.entries.len();
+        let so = min(scrolloff, height.saturating_sub(1) / 2);
+        let mut offset = self.state.offset();
+        if let Some(sel) = self.state.selected() {
+            if sel < offset + so {
+                offset = sel.saturating_sub(so);
+            } else if sel + so + 1 > offset + height {
+                offset = sel + so + 1 - height;
+            }
+        }
+        offset = min(offset, len.saturating_sub(height));
+        *self.state.offset_mut() = offset;
+
+        let mut error = None;
+        for i in offset..min(offset + height, len) {
+            if self.cache[i].is_none() {
+                let entry = &self.entries[i];
+                let fmt = Format::from_entry(entry, 0).unwrap_or_else(|err| {
+                    error = Some(err.to_string());
+                    Format::from(entry.display_name().as_str())
+                });
+                self.cache[i] = Some(fmt);
+            }
+        }
+        error
+    }
+}
+
+impl ratatui::widgets::Widget for &Listing {
+    fn render(self, area: Rect, buf: &mut ratatui::prelude::Buffer) {
+        let offset = self.state.offset();
+        let rows = self.cache.iter().skip(offset).take(area.height as usize);
+        for (i, fmt) in rows.enumerate() {
+            let row = Rect {
+                y: area.y + i as u16,
+                height: 1,
+                ..area
+            };
+            match fmt {
+                Some(fmt) => fmt.render(row, buf),
+                None => {
+                    Format::from(self.entries[offset + i].display_name().as_str()).render(row, buf)
+                }
+            }
+            if self.state.selected() == Some(offset + i) {
+                buf.set_style(
+                    row,
+                    style::Style::new().add_modifier(style::Modifier::REVERSED),
+                );
+            }
+        }
+    }
+}
+
+/// an operation waiting on the bottom line for what to do
+enum Prompt {
+    Rename(String),
+    NewFile(String),
+    NewDir(String),
+    /// removes the path once confirmed
+    Delete(PathBuf),
+    /// the paste would replace this entry
+    Conflict(PathBuf),
+}
+
+/// what `p` pastes in the current directory
+struct Clipboard {
+    path: PathBuf,
+    /// moved instead of copied
+    cut: bool,
+}
+
 pub struct Explorer {
-    cache: Vec<Format>,
+    args: ExplorerArgs,
     cwd: PathBuf,
-    state: ListState,
+    current: Listing,
+    /// only read when the layout has a parent column
+    parent: Option<Listing>,
+    /// rows in the last drawn viewport, for paging
+    height: usize,
+    /// the entry selected in each visited directory, restored when coming back
+    positions: HashMap<PathBuf, PathBuf>,
+    /// the last problem, shown on the bottom line until the next action
+    status: Option<String>,
+    previews: Sender<Request>,
+    previewed: Receiver<Preview>,
+    /// the last preview asked for, older ones are dropped when they arrive
+    requested: Option<Request>,
+    preview: Option<Preview>,
+    prompt: Option<Prompt>,
+    clipboard: Option<Clipboard>,
+}
+
+/// where each part of the explorer is drawn, the columns that don't fit are
+/// left out
+struct Layout {
+    title: Rect,
+    parent: Option<Rect>,
+    list: Rect,
+    preview: Option<Rect>,
+    status: Rect,
+}
+
+impl Layout {
+    /// `has_parent` is false when there is no parent listing, eg: at `/`, its
+    /// column is dropped then
+    fn new(area: Rect, has_parent: bool) -> Self {
+        let body = Rect {
+            y: area.y + min(area.height, 1),
+            height: area.height.saturating_sub(2),
+            ..area
+        };
+
+        // (parent, list, preview) ratios
+        let columns = COLUMNS.lock().unwrap();
+        let (mut parent, list, mut preview) = match columns[..] {
+            [parent, list, preview] => (Some(parent), list, Some(preview)),
+            [list, preview] => (None, list, Some(preview)),
+            [list, ..] => (None, list, None),
+            [] => (None, 1, None),
+        };
+        drop(columns);
+        parent = parent.filter(|_| has_parent);
+
+        loop {
+            let ratios: Vec<u32> = [parent, Some(list), preview]
+                .into_iter()
+                .flatten()
+                .collect();
+            let total: u32 = ratios.iter().sum();
+            let rects = Split::horizontal(ratios.iter().map(|&r| Constraint::Ratio(r, total)))
+                .spacing(1)
+                .split(body);
+
+            let narrow = rects.iter().any(|r| r.width < MIN_COLUMN);
+            if narrow && parent.is_some() {
+                parent = None;
+            } else if narrow && preview.is_some() {
+                preview = None;
+            } else {
+                let mut rects = rects.iter().copied();
+                return Layout {
+                    title: Rect {
+                        height: min(area.height, 1),
+                        ..area
+                    },
+                    parent: parent.and_then(|_| rects.next()),
+                    list: rects.next().unwrap_or(body),
+                    preview: preview.and_then(|_| rects.next()),
+                    status: Rect {
+                        y: area.y + area.height.saturating_sub(1),
+                        height: min(area.height, 1),
+                        ..area
+                    },
+                };
+            }
+        }
+    }
+
+    /// the columns between the panes
+    fn borders(&self) -> impl Iterator<Item = Rect> {
+        let left = [self.parent, self.preview.map(|_| self.list)];
+        left.into_iter().flatten().map(|r| Rect {
+            x: r.right(),
+            width: 1,
+            ..r
+        })
+    }
 }
 
 impl Explorer {
-    pub fn new() -> Self {
-        let cwd = current_dir().unwrap();
-        let cache = read_dir(&cwd, false, 0)
-            .unwrap()
-            .into_iter()
-            .map(|k| Format::try_from(k).unwrap())
-            .collect();
-
-        Explorer {
+    pub fn new(args: ExplorerArgs) -> Self {
+        let path = &get_options().path;
+        let cwd = path.canonicalize().unwrap_or_else(|_| path.clone());
+        let (previews, previewed) = preview::spawn();
+        let mut ex = Explorer {
+            args,
             cwd,
-            cache,
-            ..Default::default()
-        }
+            current: Listing::default(),
+            parent: None,
+            height: 0,
+            positions: HashMap::new(),
+            status: None,
+            previews,
+            previewed,
+            requested: None,
+            preview: None,
+            prompt: None,
+            clipboard: None,
+        };
+        ex.update();
+        ex
     }
 
     pub fn render(self) -> Result<()> {
         render(self)
     }
 
-    pub fn update(&mut self) {}
+    /// reads the current directory again, the rows are formatted through the
+    /// lua registry when they get drawn. The selection follows the remembered
+    /// entry, in the parent it is the current directory
+    pub fn update(&mut self) {
+        // the commands run by the formatters may say something else by now
+        clear_cache();
+        self.current = Listing::read(&self.cwd).unwrap_or_else(|err| {
+            self.status = Some(err.to_string());
+            Listing::default()
+        });
+        // the entries that couldn't be read are left out, the first says why
+        if let Some(err) = take_errors().into_iter().next() {
+            self.status = Some(err.to_string());
+        }
+        self.current
+            .select_path(self.positions.get(&self.cwd), true);
 
-    pub fn move_up(&mut self) {
-        if let Some(s) = self.state.selected_mut() {
-            *s += 1;
-        } else {
-            self.state.select(Some(0));
+        self.parent = match self.cwd.parent() {
+            Some(parent) if COLUMNS.lock().unwrap().len() > 2 => {
+                Listing::read(&parent.to_path_buf()).ok()
+            }
+            _ => None,
+        };
+        if let Some(parent) = &mut self.parent {
+            parent.select_path(Some(&self.cwd), false);
         }
+        // the parent is only there for context, its problems are not shown
+        take_errors();
+        // what is previewed may have changed too
+        self.requested = None;
     }
 
-    pub fn move_down(&mut self) {
-        if let Some(s) = self.state.selected_mut() {
-            if *s > 0 {
-                *s -= 1;
+    /// scrolls the columns to their selection in the sizes they are drawn at
+    fn scroll(&mut self, layout: &Layout) {
+        let so = self.args.scrolloff;
+        self.height = layout.list.height as usize;
+        if let Some(err) = self.current.scroll(self.height, so) {
+            self.status = Some(err);
+        }
+        // like when it is read, the parent's problems are not shown
+        if let (Some(parent), Some(area)) = (&mut self.parent, layout.parent) {
+            parent.scroll(area.height as usize, so);
+        }
+    }
+
+    /// asks for the preview of the selection in a pane of that size, unless it
+    /// already was
+    pub fn request_preview(&mut self, area: Option<Rect>) {
+        let req = match (self.current.selected(), area) {
+            (Some(entry), Some(area)) if area.width > 0 && area.height > 0 => Request {
+                path: entry.path.clone(),
+                width: area.width as usize,
+                height: area.height as usize,
+            },
+            _ => {
+                self.requested = None;
+                return;
             }
+        };
+        if self.requested.as_ref() != Some(&req) {
+            if let Err(SendError(req)) = self.previews.send(req.clone()) {
+                self.restart_previews();
+                let _ = self.previews.send(req);
+            }
+            self.requested = Some(req);
+        }
+    }
+
+    /// a worker that died, eg: on a panicking previewer, is started again.
+    /// What it was making is not asked for again, it would only die again
+    fn restart_previews(&mut self) {
+        (self.previews, self.previewed) = preview::spawn();
+    }
+
+    /// takes the previews made since the last call, true if one of them is
+    /// the one waited for
+    pub fn poll_previews(&mut self) -> bool {
+        let mut arrived = false;
+        loop {
+            match self.previewed.try_recv() {
+                Ok(preview) => {
+                    if self.requested.as_ref() == Some(&preview.req) {
+                        self.preview = Some(preview);
+                        arrived = true;
+                    }
+                }
+                Err(TryRecvError::Disconnected) => {
+                    self.restart_previews();
+                    if let Some(req) = self.requested.clone() {
+                        let lines = preview::error("the previewer stopped unexpectedly");
+                        self.preview = Some(Preview { req, lines });
+                        arrived = true;
+                    }
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        arrived
+    }
+
+    fn remember(&mut self) {
+        if let Some(path) = self.current.selected().map(|e| e.path.clone()) {
+            self.positions.insert(self.cwd.clone(), path);
+        }
+    }
+
+    fn change_dir(&mut self, dir: PathBuf) {
+        // nothing changes when the directory can't be opened
+        if let Err(err) = std::fs::read_dir(&dir) {
+            self.status = Some(format!(
+                "cannot open directory '{}': {err}",
+                quote(dir.as_os_str(), get_options().quoting_style)
+            ));
+            return;
+        }
+        self.remember();
+        self.cwd = dir;
+        self.update();
+    }
+
+    /// enters the selected directory, symlinks to directories included
+    pub fn enter(&mut self) {
+        let Some(entry) = self.current.selected() else {
+            return;
+        };
+        if entry.ty == EntryType::Dir || (entry.ty == EntryType::SymLink && entry.path.is_dir()) {
+            let dir = entry.path.clone();
+            self.change_dir(dir);
+        }
+    }
+
+    /// goes to the parent directory with the one we left selected
+    pub fn leave(&mut self) {
+        let Some(parent) = self.cwd.parent().map(PathBuf::from) else {
+            return;
+        };
+        self.positions.insert(parent.clone(), self.cwd.clone());
+        self.change_dir(parent);
+    }
+
+    pub fn rename(&mut self) {
+        if let Some(entry) = self.current.selected() {
+            self.prompt = Some(Prompt::Rename(entry.name.clone()));
+        }
+    }
+
+    pub fn new_file(&mut self) {
+        self.prompt = Some(Prompt::NewFile(String::new()));
+    }
+
+    pub fn new_dir(&mut self) {
+        self.prompt = Some(Prompt::NewDir(String::new()));
+    }
+
+    pub fn delete(&mut self) {
+        if let Some(entry) = self.current.selected() {
+            self.prompt = Some(Prompt::Delete(entry.path.clone()));
+        }
+    }
+
+    /// puts the selected entry in the clipboard, to be copied or moved by the
+    /// next paste
+    pub fn yank(&mut self, cut: bool) {
+        if let Some(entry) = self.current.selected() {
+            self.clipboard = Some(Clipboard {
+                path: entry.path.clone(),
+                cut,
+            });
+        }
+    }
+
+    /// asks first when something is in the way
+    pub fn paste(&mut self) {
+        let Some(clip) = &self.clipboard else {
+            return;
+        };
+        match fileops::conflict(&clip.path, &self.cwd) {
+            Some(dst) => self.prompt = Some(Prompt::Conflict(dst)),
+            None => self.paste_with(OnConflict::Skip),
+        }
+    }
+
+    fn paste_with(&mut self, on_conflict: OnConflict) {
+        let Some(clip) = &self.clipboard else {
+            return;
+        };
+        let done = if clip.cut {
+            fileops::move_into(&clip.path, &self.cwd, on_conflict)
         } else {
-            self.state.select(Some(0));
+            fileops::copy(&clip.path, &self.cwd, on_conflict)
+        };
+        // a cut entry isn't where it was anymore
+        if clip.cut && matches!(done, Ok(Some(_))) {
+            self.clipboard = None;
+        }
+        self.done(done);
+    }
+
+    /// reloads after an operation, selecting the entry it made
+    fn done(&mut self, done: Result<Option<PathBuf>, OpError>) {
+        match done {
+            Ok(Some(path)) => {
+                self.positions.insert(self.cwd.clone(), path);
+            }
+            Ok(None) => {}
+            Err(err) => self.status = Some(err.to_string()),
+        }
+        self.update();
+    }
+
+    /// handles the keys while a prompt is shown, Esc cancels them all
+    pub fn prompt_key(&mut self, code: KeyCode) {
+        let Some(mut prompt) = self.prompt.take() else {
+            return;
+        };
+        match (&mut prompt, code) {
+            (_, KeyCode::Esc) => {}
+            (Prompt::Rename(text) | Prompt::NewFile(text) | Prompt::NewDir(text), code) => {
+                match code {
+                    KeyCode::Enter => return self.run(prompt),
+                    KeyCode::Char(c) => text.push(c),
+                    KeyCode::Backspace => {
+                        text.pop();
+                    }
+                    _ => {}
+                }
+                self.prompt = Some(prompt);
+            }
+            // anything but yes keeps the entry
+            (Prompt::Delete(_), KeyCode::Char('y')) => self.run(prompt),
+            (Prompt::Delete(_), _) => {}
+            (Prompt::Conflict(_), KeyCode::Char('o')) => self.paste_with(OnConflict::Overwrite),
+            (Prompt::Conflict(_), KeyCode::Char('s')) => self.paste_with(OnConflict::Skip),
+            (Prompt::Conflict(_), KeyCode::Char('r')) => self.paste_with(OnConflict::Rename),
+            (Prompt::Conflict(_), _) => self.prompt = Some(prompt),
+        }
+    }
+
+    fn run(&mut self, prompt: Prompt) {
+        match prompt {
+            Prompt::Rename(name) => {
+                if let Some(path) = self.current.selected().map(|e| e.path.clone()) {
+                    self.done(fileops::rename(&path, &name).map(Some));
+                }
+            }
+            Prompt::NewFile(name) => self.done(fileops::create_file(&self.cwd, &name).map(Some)),
+            Prompt::NewDir(name) => self.done(fileops::create_dir(&self.cwd, &name).map(Some)),
+            Prompt::Delete(path) => {
+                // the selection stays on the same row
+                let row = self.current.state.selected();
+                self.done(fileops::remove(&path).map(|_| None));
+                if let Some(row) = row {
+                    self.current.select(row);
+                }
+            }
+            Prompt::Conflict(_) => {}
+        }
+    }
+
+    /// what the bottom line asks, with the text typed so far
+    fn prompt_text(&self) -> Option<String> {
+        let name = |path: &PathBuf| {
+            let name = path.file_name().unwrap_or(path.as_os_str());
+            quote(name, get_options().quoting_style)
+        };
+        let text = match self.prompt.as_ref()? {
+            Prompt::Rename(text) => format!("rename: {text}"),
+            Prompt::NewFile(text) => format!("new file: {text}"),
+            Prompt::NewDir(text) => format!("new directory: {text}"),
+            Prompt::Delete(path) => format!("delete '{}'? (y/N)", name(path)),
+            // nothing is merged, the whole directory goes
+            Prompt::Conflict(path) if path.is_dir() && !path.is_symlink() => format!(
+                "directory '{}' already exists, overwriting replaces all of it: (o)verwrite, (s)kip, (r)ename",
+                name(path)
+            ),
+            Prompt::Conflict(path) => format!(
+                "'{}' already exists: (o)verwrite, (s)kip, (r)ename",
+                name(path)
+            ),
+        };
+        Some(text)
+    }
+
+    pub fn move_up(&mut self) {
+        self.move_by(-1);
+    }
+
+    pub fn move_down(&mut self) {
+        self.move_by(1);
+    }
+
+    pub fn page_up(&mut self) {
+        self.move_by(-(self.height.max(1) as isize));
+    }
+
+    pub fn page_down(&mut self) {
+        self.move_by(self.height.max(1) as isize);
+    }
+
+    fn move_by(&mut self, n: isize) {
+        if let Some(s) = self.current.state.selected() {
+            self.current.select(s.saturating_add_signed(n));
         }
     }
+
+    pub fn first(&mut self) {
+        self.current.select(0);
+    }
+
+    pub fn last(&mut self) {
+        self.current.select(usize::MAX);
+    }
 }
 
 impl ratatui::widgets::Widget for &Explorer {
@@ -94,12 +689,81 @@ impl ratatui::widgets::Widget for &Explorer {
     where
         Self: Sized,
     {
-        let size = area;
-        for i in 0..min(size.height, self.cache.len() as u16) {
-            let mut s = area.clone();
-            s.height = 1;
-            s.y = area.y + i;
-            self.cache[i as usize].render(s, buf);
+        if area.height < 3 {
+            return;
+        }
+        let layout = Layout::new(area, self.parent.is_some());
+        let title = quote(self.cwd.as_os_str(), get_options().quoting_style);
+        buf.set_stringn(
+            layout.title.x,
+            layout.title.y,
+            title,
+            layout.title.width as usize,
+            style::Style::new().add_modifier(style::Modifier::BOLD),
+        );
+        if let Some(clip) = &self.clipboard {
+            let name = clip.path.file_name().unwrap_or(clip.path.as_os_str());
+            let op = if clip.cut { "cut" } else { "copy" };
+            let text = format!(" {op} '{}'", quote(name, get_options().quoting_style));
+            let width = min(text.width() as u16, layout.title.width);
+            buf.set_stringn(
+                layout.title.right() - width,
+                layout.title.y,
+                text,
+                width as usize,
+                style::Style::new().add_modifier(style::Modifier::DIM),
+            );
+        }
+        if let Some(text) = self.prompt_text() {
+            let (x, _) = buf.set_stringn(
+                layout.status.x,
+                layout.status.y,
+                text,
+                layout.status.width as usize,
+                style::Style::new(),
+            );
+            // where the next character goes
+            if let Some(cell) = buf.cell_mut(Position::new(x, layout.status.y)) {
+                cell.set_style(style::Style::new().add_modifier(style::Modifier::REVERSED));
+            }
+        } else if let Some(status) = &self.status {
+            buf.set_stringn(
+                layout.status.x,
+                layout.status.y,
+                status,
+                layout.status.width as usize,
+                style::Style::new().fg(style::Color::Red),
+            );
+        }
+
+        if let (Some(parent), Some(pane)) = (&self.parent, layout.parent) {
+            parent.render(pane, buf);
+        }
+        self.current.render(layout.list, buf);
+
+        for border in layout.borders() {
+            for y in border.top()..border.bottom() {
+                if let Some(cell) = buf.cell_mut(Position::new(border.x, y)) {
+                    cell.set_symbol("│");
+                    cell.set_style(style::Style::new().add_modifier(style::Modifier::DIM));
+                }
+            }
+        }
+
+        // a preview still on its way leaves the pane empty
+        let preview = self
+            .preview
+            .as_ref()
+            .filter(|p| self.requested.as_ref() == Some(&p.req));
+        if let (Some(preview), Some(pane)) = (preview, layout.preview) {
+            for (i, line) in preview.lines.iter().take(pane.height as usize).enumerate() {
+                let row = Rect {
+                    y: pane.y + i as u16,
+                    height: 1,
+                    ..pane
+                };
+                line.render(row, buf);
+            }
         }
     }
 }
@@ -111,37 +775,55 @@ fn render(mut ex: Explorer) -> Result<()> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
+    // only drawn again after an event or once the preview arrived
+    let mut dirty = true;
     'render: loop {
-        terminal.draw(|f| {
-            let size = f.area();
-            f.render_widget(&ex, size);
-        })?;
+        dirty |= ex.poll_previews();
+        if dirty {
+            terminal.draw(|f| {
+                let size = f.area();
+                let layout = Layout::new(size, ex.parent.is_some());
+                ex.scroll(&layout);
+                ex.request_preview(layout.preview);
+                f.render_widget(&ex, size);
+            })?;
+            dirty = false;
+        }
+        if !event::poll(Duration::from_millis(100 / 6))? {
+            continue;
+        }
         let e = event::read()?;
+        dirty = true;
         use crossterm::event as ce;
         use crossterm::event::Event as cE;
-        match e {
-            cE::Key(k) => match k.code {
-                ce::KeyCode::Esc => {
-                    break 'render;
-                }
-                ce::KeyCode::Char(c) => match c {
-                    'k' => {
-                        ex.move_up();
-                    }
-                    'j' => {
-                        ex.move_down();
-                    }
-                    'q' => {
-                        break 'render;
-                    }
-                    _ => {}
-                },
+        if let cE::Key(k) = e {
+            // the status is only about the last action
+            ex.status = None;
+            if ex.prompt.is_some() {
+                ex.prompt_key(k.code);
+                continue;
+            }
+            match k.code {
+                ce::KeyCode::Esc | ce::KeyCode::Char('q') => break 'render,
+                ce::KeyCode::Up | ce::KeyCode::Char('k') => ex.move_up(),
+                ce::KeyCode::Down | ce::KeyCode::Char('j') => ex.move_down(),
+                ce::KeyCode::Enter | ce::KeyCode::Right | ce::KeyCode::Char('l') => ex.enter(),
+                ce::KeyCode::Backspace | ce::KeyCode::Left | ce::KeyCode::Char('h') => ex.leave(),
+                ce::KeyCode::PageUp => ex.page_up(),
+                ce::KeyCode::PageDown => ex.page_down(),
+                ce::KeyCode::Home | ce::KeyCode::Char('g') => ex.first(),
+                ce::KeyCode::End | ce::KeyCode::Char('G') => ex.last(),
+                ce::KeyCode::Char('R') => ex.update(),
+                ce::KeyCode::Char('r') => ex.rename(),
+                ce::KeyCode::Char('n') => ex.new_file(),
+                ce::KeyCode::Char('N') => ex.new_dir(),
+                ce::KeyCode::Char('y') => ex.yank(false),
+                ce::KeyCode::Char('d') => ex.yank(true),
+                ce::KeyCode::Char('p') => ex.paste(),
+                ce::KeyCode::Delete | ce::KeyCode::Char('D') => ex.delete(),
                 _ => {}
-            },
-            _ => {}
+            }
         }
-
-        thread::sleep(Duration::from_millis(100 / 6));
     }
 
     // restore terminal
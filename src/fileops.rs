@@ -0,0 +1,365 @@
+use std::{
+    ffi::{CString, OsStr, OsString},
+    fs::{self, Metadata, OpenOptions},
+    io,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, MetadataExt, symlink},
+    },
+    path::{Path, PathBuf},
+};
+
+use crate::error::OpError;
+
+/// what to do when the destination of a copy or move is taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnConflict {
+    /// the destination is replaced once the new entry is complete,
+    /// directories are replaced whole and never merged
+    Overwrite,
+    Skip,
+    /// the first free `name (n).ext` is used instead
+    Rename,
+}
+
+/// dangling links are there too
+fn exists(path: &Path) -> bool {
+    path.symlink_metadata().is_ok()
+}
+
+/// the path with its directory resolved, the last component is left alone so
+/// links are never followed
+fn resolved(path: &Path) -> PathBuf {
+    match (path.parent().map(fs::canonicalize), path.file_name()) {
+        (Some(Ok(dir)), Some(name)) => dir.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// a name is a single component
+fn check_name(name: &str) -> Result<(), OpError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+        return Err(OpError::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// `a` for `a (2)`, the name itself if it has no suffix
+fn strip_suffix(stem: &[u8]) -> &[u8] {
+    let Some(inner) = stem.strip_suffix(b")") else {
+        return stem;
+    };
+    let digits = inner
+        .iter()
+        .rev()
+        .take_while(|b| b.is_ascii_digit())
+        .count();
+    match inner[..inner.len() - digits].strip_suffix(b" (") {
+        Some(base) if digits > 0 && !base.is_empty() => base,
+        _ => stem,
+    }
+}
+
+/// `a.tar.gz` gives `a (1).tar.gz`, `a (2).tar.gz`... the first that is free.
+/// Copies of copies count from the original name
+fn with_suffix(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().as_bytes();
+    // the dot of hidden files doesn't start the extension
+    let start = usize::from(name.first() == Some(&b'.'));
+    let split = name[start..]
+        .iter()
+        .position(|&b| b == b'.')
+        .map_or(name.len(), |i| start + i);
+    let (stem, ext) = name.split_at(split);
+    let stem = strip_suffix(stem);
+
+    (1..)
+        .map(|n| {
+            let mut name = stem.to_vec();
+            name.extend_from_slice(format!(" ({n})").as_bytes());
+            name.extend_from_slice(ext);
+            path.with_file_name(OsStr::from_bytes(&name))
+        })
+        .find(|path| !exists(path))
+        .unwrap()
+}
+
+/// where `src` goes in `dir`
+fn target(src: &Path, dir: &Path) -> PathBuf {
+    dir.join(src.file_name().unwrap_or(src.as_os_str()))
+}
+
+/// the entry a copy or move of `src` into `dir` would replace, never `src`
+/// itself
+pub fn conflict(src: &Path, dir: &Path) -> Option<PathBuf> {
+    let dst = resolved(&target(src, dir));
+    (exists(&dst) && dst != resolved(src)).then_some(dst)
+}
+
+/// where a copy or move lands
+struct Destination {
+    path: PathBuf,
+    /// an existing entry is to be overwritten
+    replaces: bool,
+}
+
+/// where `src` lands in `dir`, `None` when it is skipped. Nothing is removed
+/// here, a directory can't replace what it is in
+fn destination(
+    src: &Path,
+    dir: &Path,
+    on_conflict: OnConflict,
+) -> Result<Option<Destination>, OpError> {
+    let src = resolved(src);
+    let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let dst = target(&src, &dir);
+    if dir.starts_with(&src) || (src.starts_with(&dst) && src != dst) {
+        return Err(OpError::Overlap { from: src, to: dst });
+    }
+    let new = |path| {
+        Some(Destination {
+            path,
+            replaces: false,
+        })
+    };
+    if !exists(&dst) {
+        return Ok(new(dst));
+    }
+    // `src` itself is only ever renamed
+    if dst == src {
+        return Ok(new(with_suffix(&dst)));
+    }
+
+    match on_conflict {
+        OnConflict::Skip => Ok(None),
+        OnConflict::Rename => Ok(new(with_suffix(&dst))),
+        OnConflict::Overwrite => Ok(Some(Destination {
+            path: dst,
+            replaces: true,
+        })),
+    }
+}
+
+/// a free hidden name next to `path` for the work in progress, eg:
+/// `.name.tmp-1`
+fn sibling(path: &Path, what: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default();
+    (1..)
+        .map(|n| {
+            let mut sibling = OsString::from(".");
+            sibling.push(name);
+            sibling.push(format!(".{what}-{n}"));
+            path.with_file_name(sibling)
+        })
+        .find(|path| !exists(path))
+        .unwrap()
+}
+
+fn is_dir(path: &Path) -> bool {
+    path.symlink_metadata().is_ok_and(|m| m.is_dir())
+}
+
+/// puts `new` in place of the existing `dst`. Files and links replace each
+/// other in a single rename, otherwise the old entry is moved aside and only
+/// removed once the new one is in. It is put back if that fails
+fn replace(new: &Path, dst: &Path) -> io::Result<()> {
+    if !is_dir(new) && !is_dir(dst) {
+        return fs::rename(new, dst);
+    }
+    let old = sibling(dst, "old");
+    fs::rename(dst, &old)?;
+    if let Err(err) = fs::rename(new, dst) {
+        let _ = fs::rename(&old, dst);
+        return Err(err);
+    }
+    remove_tree(&old)
+}
+
+/// copies `src` next to the destination then renames it there, a copy that
+/// fails half way is removed and never touches what was there
+fn copy_in(src: &Path, dst: &Destination) -> io::Result<()> {
+    let tmp = sibling(&dst.path, "tmp");
+    let copied = copy_tree(src, &tmp).and_then(|_| match dst.replaces {
+        true => replace(&tmp, &dst.path),
+        false => fs::rename(&tmp, &dst.path),
+    });
+    if copied.is_err() {
+        let _ = remove_tree(&tmp);
+    }
+    copied
+}
+
+fn set_times(path: &Path, meta: &Metadata) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let times = [
+        libc::timespec {
+            tv_sec: meta.atime(),
+            tv_nsec: meta.atime_nsec(),
+        },
+        libc::timespec {
+            tv_sec: meta.mtime(),
+            tv_nsec: meta.mtime_nsec(),
+        },
+    ];
+    // SAFETY: the path is NUL terminated and `times` holds the access and
+    // modification times utimensat reads
+    let ret = unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn mkfifo(path: &Path, mode: u32) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: the path is NUL terminated
+    if unsafe { libc::mkfifo(path.as_ptr(), mode as libc::mode_t) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// symlinks are copied as links, the permissions and timestamps are kept.
+/// Directories get theirs once everything is copied in them
+fn copy_tree(src: &Path, dst: &Path) -> io::Result<()> {
+    let meta = src.symlink_metadata()?;
+    let ft = meta.file_type();
+    if ft.is_symlink() {
+        symlink(fs::read_link(src)?, dst)?;
+    } else if ft.is_dir() {
+        fs::create_dir(dst)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+    } else if ft.is_file() {
+        fs::copy(src, dst)?;
+    } else if ft.is_fifo() {
+        mkfifo(dst, meta.mode())?;
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "devices and sockets can't be copied",
+        ));
+    }
+
+    if !ft.is_symlink() {
+        fs::set_permissions(dst, meta.permissions())?;
+    }
+    set_times(dst, &meta)
+}
+
+fn remove_tree(path: &Path) -> io::Result<()> {
+    if path.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// copies `src` into `dir`, the path of the copy is returned unless skipped
+pub fn copy(src: &Path, dir: &Path, on_conflict: OnConflict) -> Result<Option<PathBuf>, OpError> {
+    let Some(dst) = destination(src, dir, on_conflict)? else {
+        return Ok(None);
+    };
+    copy_in(src, &dst).map_err(|source| OpError::Copy {
+        from: src.to_path_buf(),
+        to: dst.path.clone(),
+        source,
+    })?;
+    Ok(Some(dst.path))
+}
+
+/// moves `src` into `dir`, across filesystems it is copied then removed.
+/// Moving something where it already is does nothing
+pub fn move_into(
+    src: &Path,
+    dir: &Path,
+    on_conflict: OnConflict,
+) -> Result<Option<PathBuf>, OpError> {
+    if resolved(&target(src, dir)) == resolved(src) {
+        return Ok(None);
+    }
+    let Some(dst) = destination(src, dir, on_conflict)? else {
+        return Ok(None);
+    };
+    let err = |source| OpError::Move {
+        from: src.to_path_buf(),
+        to: dst.path.clone(),
+        source,
+    };
+
+    let moved = match dst.replaces {
+        true => replace(src, &dst.path),
+        false => fs::rename(src, &dst.path),
+    };
+    match moved {
+        Ok(()) => {}
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            copy_in(src, &dst).map_err(err)?;
+            remove_tree(src).map_err(err)?;
+        }
+        Err(e) => return Err(err(e)),
+    }
+    Ok(Some(dst.path))
+}
+
+/// removes the entry, directories with everything in them. Links are removed,
+/// not what they point to
+pub fn remove(path: &Path) -> Result<(), OpError> {
+    remove_tree(path).map_err(|source| OpError::Remove {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// renames the entry in its directory, an existing entry is never replaced
+pub fn rename(path: &Path, name: &str) -> Result<PathBuf, OpError> {
+    check_name(name)?;
+    let dst = path.with_file_name(name);
+    if dst == path {
+        return Ok(dst);
+    }
+    let err = |source| OpError::Rename {
+        from: path.to_path_buf(),
+        to: dst.clone(),
+        source,
+    };
+    if exists(&dst) {
+        return Err(err(io::ErrorKind::AlreadyExists.into()));
+    }
+    fs::rename(path, &dst).map_err(err)?;
+    Ok(dst)
+}
+
+/// an empty file, it must not exist already
+pub fn create_file(dir: &Path, name: &str) -> Result<PathBuf, OpError> {
+    check_name(name)?;
+    let path = dir.join(name);
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|source| OpError::Create {
+            path: path.clone(),
+            source,
+        })?;
+    Ok(path)
+}
+
+pub fn create_dir(dir: &Path, name: &str) -> Result<PathBuf, OpError> {
+    check_name(name)?;
+    let path = dir.join(name);
+    fs::create_dir(&path).map_err(|source| OpError::Create {
+        path: path.clone(),
+        source,
+    })?;
+    Ok(path)
+}
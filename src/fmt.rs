@@ -1,22 +1,90 @@
-use crate::*;
+use std::{
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-fn _format_file<'a>(map: &'a HashMap<FileType, Function>, path: &PathBuf) -> Option<&'a Function> {
-    let extension = path.extension()?.to_str()?.to_string();
-    let ft = FileType::OtherFile(extension.clone());
+use crate::{magic::mime_type, *};
 
-    return map.get(&ft);
+/// extensions are looked up in lowercase, set from `load_formats`
+pub static EXTENSIONS_IGNORE_CASE: AtomicBool = AtomicBool::new(false);
+
+/// every extension of the name, longest first: `a.tar.gz` gives `tar.gz` then
+/// `gz`. the dot of hidden files doesn't start one
+fn extensions(name: &str) -> impl Iterator<Item = &str> {
+    let name = name.strip_prefix('.').unwrap_or(name);
+    name.match_indices('.')
+        .map(move |(i, _)| &name[i + 1..])
+        .filter(|ext| !ext.is_empty())
+}
+
+pub fn _format_file<'a>(
+    map: &'a HashMap<FileType, Function>,
+    path: &PathBuf,
+) -> Option<&'a Function> {
+    let name = path.file_name()?.to_str()?;
+    let ignore_case = EXTENSIONS_IGNORE_CASE.load(Ordering::Relaxed);
+
+    extensions(name).find_map(|ext| {
+        let ext = if ignore_case {
+            ext.to_lowercase()
+        } else {
+            ext.to_string()
+        };
+        map.get(&FileType::OtherFile(ext))
+    })
+}
+
+/// set once `load_formats` is given a format keyed on a MIME type, until
+/// then no file is ever read to find its type
+pub static MIME_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// the format of the MIME type of the file, eg: `image/png` then `image/*`
+pub fn _format_mime<'a>(
+    map: &'a HashMap<FileType, Function>,
+    entry: &Entry,
+) -> Option<&'a Function> {
+    if !MIME_ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    by_mime(map, entry)
+}
+
+/// what is registered for the MIME type of the file, it is always read
+pub fn by_mime<'a>(map: &'a HashMap<FileType, Function>, entry: &Entry) -> Option<&'a Function> {
+    let mime = mime_type(&entry.path, (entry.dev, entry.ino, entry.mtime))?;
+    let wildcard = format!("{}/*", mime.split('/').next()?);
+
+    map.get(&FileType::Mime(mime))
+        .or_else(|| map.get(&FileType::Mime(wildcard)))
 }
 
-pub fn format_file(path: &PathBuf) -> Function {
+/// by extension, then by content when MIME formats are registered
+pub fn format_file(entry: &Entry) -> Function {
     let map = MAP.lock().unwrap();
-    let format = _format_file(&map, path).unwrap_or(map.get(&FileType::GenericFile).unwrap());
+    let format = _format_file(&map, &entry.path)
+        .or_else(|| _format_mime(&map, entry))
+        .unwrap_or(map.get(&FileType::GenericFile).unwrap());
 
     return format.clone();
 }
 
-pub fn format_dir(_: &PathBuf) -> Function {
+/// executables keep the style of their extension or content if there is one
+pub fn format_exec(entry: &Entry) -> Function {
     let map = MAP.lock().unwrap();
-    let format = map.get(&FileType::GenericDir).unwrap();
+    let format = _format_file(&map, &entry.path)
+        .or_else(|| _format_mime(&map, entry))
+        .unwrap_or(map.get(&FileType::GenericExecutable).unwrap());
+
+    return format.clone();
+}
+
+/// directories are looked up by their name
+pub fn format_dir(path: &Path) -> Function {
+    let map = MAP.lock().unwrap();
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+    let format = name
+        .and_then(|n| map.get(&FileType::OtherDir(n)))
+        .unwrap_or(map.get(&FileType::GenericDir).unwrap());
 
     return format.clone();
 }
@@ -27,3 +95,11 @@ pub fn format_link(_: &PathBuf) -> Function {
 
     return format.clone();
 }
+
+/// formatter of the types that can only be styled as a whole
+pub fn format_generic(ft: &FileType) -> Function {
+    let map = MAP.lock().unwrap();
+    let format = map.get(ft).unwrap();
+
+    return format.clone();
+}
@@ -0,0 +1,90 @@
+/// a `[...]` set of characters
+struct Class {
+    /// index right after the closing `]`
+    end: usize,
+    negated: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl Class {
+    fn contains(&self, c: char) -> bool {
+        self.ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != self.negated
+    }
+}
+
+/// the class starting at `p[start]`, `None` if it is never closed and the `[`
+/// is a plain character
+fn class(p: &[char], start: usize) -> Option<Class> {
+    let mut i = start + 1;
+    let negated = matches!(p.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = *p.get(i)?;
+        // a `]` right after the opening is part of the set
+        if c == ']' && !first {
+            return Some(Class {
+                end: i + 1,
+                negated,
+                ranges,
+            });
+        }
+        first = false;
+        if p.get(i + 1) == Some(&'-') && p.get(i + 2).is_some_and(|c| *c != ']') {
+            ranges.push((c, p[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+}
+
+/// shell style wildcards: `*`, `?`, `[abc]`, `[a-z]` and `[!abc]`
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+
+    let (mut pi, mut ni) = (0, 0);
+    // where to resume after the last `*` when the rest doesn't match
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        let matched = match p.get(pi) {
+            Some('*') => {
+                star = Some((pi, ni));
+                pi += 1;
+                continue;
+            }
+            Some('?') => Some(pi + 1),
+            Some('[') => match class(&p, pi) {
+                Some(class) => class.contains(n[ni]).then_some(class.end),
+                None => (n[ni] == '[').then_some(pi + 1),
+            },
+            Some(c) => (*c == n[ni]).then_some(pi + 1),
+            None => None,
+        };
+
+        match (matched, star) {
+            (Some(next), _) => {
+                pi = next;
+                ni += 1;
+            }
+            (None, Some((sp, sn))) => {
+                pi = sp + 1;
+                ni = sn + 1;
+                star = Some((sp, sn + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+pub fn has_wildcards(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
@@ -1,6 +1,17 @@
-use std::{cmp::Ordering, path::PathBuf, process::exit};
+use std::{
+    io::{IsTerminal, stdout},
+    path::PathBuf,
+    process::exit,
+};
 
-use crate::{get_options, util::*};
+use crate::{
+    error::report,
+    get_options,
+    output::{Output, OutputFormat},
+    quote::quote,
+    sort::sort_entries,
+    util::*,
+};
 
 use anyhow::Result;
 use clap::Parser;
@@ -18,9 +29,6 @@ fn curr_dir() -> Vec<PathBuf> {
 
 #[derive(Debug, Parser, Clone)]
 pub struct List {
-    #[arg(value_enum, long, short, default_value_t=SortBy::Name)]
-    sort_by: SortBy,
-
     #[arg(long, short, default_value_t = 0)]
     recursive: u64,
 
@@ -30,44 +38,260 @@ pub struct List {
     #[arg(long, default_value_t = false)]
     all: bool,
 
+    /// follow symlinks, showing and recursing into what they point to
+    #[arg(short = 'L', long, default_value_t = false)]
+    dereference: bool,
+
+    /// draw the recursive tree with ascii characters instead of box drawing ones
+    #[arg(long, default_value_t = false)]
+    ascii: bool,
+
+    /// fill the grid column by column (default)
+    #[arg(short = 'C', long, default_value_t = false, overrides_with = "across")]
+    columns: bool,
+
+    /// fill the grid row by row
+    #[arg(short = 'x', long, default_value_t = false, overrides_with = "columns")]
+    across: bool,
+
+    /// long listing: permissions, links, owner, group, size and modification time
+    #[arg(long, default_value_t = false)]
+    long: bool,
+
+    /// machine readable output, the lua formatters are skipped
+    #[arg(value_enum, long, short)]
+    output: Option<OutputFormat>,
+
+    /// add the text of the lua formatted name to the machine readable output
+    #[arg(long, default_value_t = false, requires = "output")]
+    rendered: bool,
+
     #[arg(default_values_os_t = curr_dir())]
     paths: Vec<PathBuf>,
 }
 
-fn sort_name(a: &Entry, b: &Entry) -> std::cmp::Ordering {
-    let v = a.name.to_lowercase().cmp(&b.name.to_lowercase());
-    if let Ordering::Equal = v {
-        return a.ty.cmp(&b.ty);
+/// a row of the long listing, every column but the name is pre rendered
+struct LongRow {
+    cols: [String; 5],
+    name: Format,
+    /// symlink target and whether it is dangling
+    target: Option<(String, bool)>,
+}
+
+impl LongRow {
+    fn new(entry: Entry) -> Result<Self> {
+        let cols = [
+            entry.mode_string(),
+            entry.nlink.to_string(),
+            entry.user.clone(),
+            entry.group.clone(),
+            match entry.ty {
+                EntryType::BlockDevice | EntryType::CharDevice => {
+                    format!("{}, {}", libc::major(entry.rdev), libc::minor(entry.rdev))
+                }
+                _ => entry.size.to_string(),
+            },
+        ];
+        let date = format_time(entry.mtime);
+        let target = entry.target.as_ref().map(|t| {
+            (
+                quote(t.as_os_str(), get_options().quoting_style),
+                entry.broken,
+            )
+        });
+        let name = Format::try_from(&entry)?;
+
+        let mut row = LongRow { cols, name, target };
+        row.cols[4] = format!("{} {date}", row.cols[4]);
+        Ok(row)
+    }
+}
+
+fn print_long(rows: &[LongRow]) {
+    let mut widths = [0; 5];
+    for row in rows {
+        for (w, col) in widths.iter_mut().zip(&row.cols) {
+            *w = (*w).max(col.chars().count());
+        }
+    }
+
+    // numbers right aligned, names left aligned
+    for row in rows {
+        let [mode, nlink, user, group, size] = &row.cols;
+        let target = match &row.target {
+            Some((target, true)) => {
+                let col = *BROKEN_LINK_COLOR.lock().unwrap();
+                format!(" -> {col}{target}{}", reset())
+            }
+            Some((target, false)) => format!(" -> {target}"),
+            None => String::new(),
+        };
+        println!(
+            "{mode:<w0$} {nlink:>w1$} {user:<w2$} {group:<w3$} {size:>w4$} {}{target}",
+            row.name,
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+            w4 = widths[4],
+        );
+    }
+}
+
+const GRID_SEP: usize = 2;
+
+/// the widths of each column when `len` entries are laid on `rows` rows
+fn grid_widths(widths: &[usize], rows: usize, across: bool) -> Vec<usize> {
+    let cols = widths.len().div_ceil(rows);
+    let mut col_widths = vec![0; cols];
+    for (i, w) in widths.iter().enumerate() {
+        let col = if across { i % cols } else { i / rows };
+        col_widths[col] = col_widths[col].max(*w);
     }
-    return v;
+    col_widths
 }
 
-fn sort_type(a: &Entry, b: &Entry) -> std::cmp::Ordering {
-    let v = a.ty.cmp(&b.ty);
-    if let Ordering::Equal = v {
-        return a.name.to_lowercase().cmp(&b.name.to_lowercase());
+/// packs the entries in the fewest rows that fit in `term_width`, like `ls -C`
+fn print_grid(v: &[Format], term_width: usize, across: bool) {
+    if v.is_empty() {
+        return;
+    }
+
+    let widths: Vec<usize> = v.iter().map(Format::width).collect();
+    let mut rows = 1;
+    let mut col_widths = grid_widths(&widths, rows, across);
+    while rows < v.len() {
+        let total = col_widths.iter().sum::<usize>() + GRID_SEP * (col_widths.len() - 1);
+        if total <= term_width {
+            break;
+        }
+        rows += 1;
+        col_widths = grid_widths(&widths, rows, across);
+    }
+
+    let cols = col_widths.len();
+    for row in 0..rows {
+        let mut line = String::new();
+        for (col, col_width) in col_widths.iter().enumerate() {
+            let i = if across {
+                row * cols + col
+            } else {
+                col * rows + row
+            };
+            let Some(e) = v.get(i) else {
+                break;
+            };
+            let last = if across {
+                col + 1 == cols || i + 1 == v.len()
+            } else {
+                i + rows >= v.len()
+            };
+
+            line.push_str(&e.to_string());
+            if !last {
+                line.push_str(&" ".repeat(col_width - widths[i] + GRID_SEP));
+            }
+        }
+        println!("{line}");
+    }
+}
+
+/// counts the directories and files below the entries, the entries included
+fn count(entries: &[Entry]) -> (usize, usize) {
+    let mut dirs = 0;
+    let mut files = 0;
+    for entry in entries {
+        if entry.ty == EntryType::Dir {
+            dirs += 1;
+        } else {
+            files += 1;
+        }
+        let (d, f) = count(&entry.childs);
+        dirs += d;
+        files += f;
     }
-    return v;
+    (dirs, files)
+}
+
+fn plural(n: usize, one: &str, many: &str) -> String {
+    format!("{n} {}", if n == 1 { one } else { many })
 }
 
 impl List {
-    fn get_formats(&self, path: &PathBuf) -> Result<Vec<Format>> {
-        let mut entries = read_dir(&path, self.all, self.recursive)?;
-        match &self.sort_by {
-            SortBy::Name => entries.sort_by(sort_name),
-            SortBy::Type => entries.sort_by(sort_type),
+    fn read_options(&self) -> ReadOptions {
+        ReadOptions {
+            hidden: self.all,
+            follow: self.dereference,
         }
+    }
 
-        let mut v = Vec::new();
-        for entry in entries {
-            v.push(Format::try_from(entry)?);
+    /// the sorted contents of `path`, empty if it can't be read
+    fn get_entries(&self, path: &PathBuf, depth: u64) -> Vec<Entry> {
+        match read_dir(path, &self.read_options(), depth) {
+            Ok(mut entries) => {
+                sort_entries(&mut entries);
+                entries
+            }
+            Err(err) => {
+                report(err);
+                Vec::new()
+            }
         }
+    }
+
+    /// like `ls -l`, what isn't a directory gets its own row and comes first,
+    /// then each directory with its contents
+    fn ls_long(&self) -> Result<()> {
+        print!("{}", reset());
+        let (dirs, others): (Vec<_>, Vec<_>) = self.paths.iter().partition(|p| p.is_dir());
 
-        return Ok(v);
+        let mut rows = Vec::new();
+        for path in &others {
+            match process_path((*path).clone(), &self.read_options(), 0) {
+                Ok(entry) => rows.push(LongRow::new(entry)?),
+                Err(err) => report(err),
+            }
+        }
+        print_long(&rows);
+
+        for (i, path) in dirs.iter().enumerate() {
+            if self.paths.len() > 1 {
+                if i > 0 || !rows.is_empty() {
+                    println!();
+                }
+                println!("{}:", quote(path.as_os_str(), get_options().quoting_style));
+            }
+
+            let mut rows = Vec::new();
+            for entry in self.get_entries(path, 0) {
+                rows.push(LongRow::new(entry)?);
+            }
+            print_long(&rows);
+        }
+
+        Ok(())
     }
 
     pub fn ls(&self) -> Result<()> {
-        let list = self.list == true || self.recursive > 0 || self.paths.len() > 1;
+        if let Some(format) = self.output {
+            let output = Output {
+                format,
+                opts: self.read_options(),
+                rendered: self.rendered,
+            };
+            return output.write(&self.paths, self.recursive);
+        }
+
+        if self.long {
+            return self.ls_long();
+        }
+
+        // like ls, pipes and files get one entry per line unless a grid is asked for
+        let grid_forced = self.columns || self.across;
+        let list = self.list == true
+            || self.recursive > 0
+            || self.paths.len() > 1
+            || (!stdout().is_terminal() && !grid_forced);
         if get_options().debug {
             println!("len: {}", self.paths.len());
             println!("rec: {}", self.recursive);
@@ -75,45 +299,72 @@ impl List {
             println!("is list: {}", list);
         }
 
-        let mut v = Vec::new();
+        // the tree is only drawn when there is something nested to show
+        let tree = self.recursive > 0 || self.paths.len() > 1;
+        let mut entries = Vec::new();
+        let (dirs, files);
         if self.paths.len() > 1 {
+            let opts = self.read_options();
             for path in &self.paths {
-                match process_path(path.clone(), self.all, 0) {
-                    Ok(k) => {
-                        let root = Format::try_from(process_path(
-                            k.path.clone(),
-                            self.all,
-                            self.recursive + 1,
-                        )?)?;
-                        v.push(root);
+                match process_path(path.clone(), &opts, self.recursive + 1) {
+                    Ok(mut root) => {
+                        sort_entries(&mut root.childs);
+                        entries.push(root);
                     }
-                    _ => {}
+                    Err(err) => report(err),
                 }
             }
+            (dirs, files) = entries
+                .iter()
+                .map(|root| count(&root.childs))
+                .fold((0, 0), |(d, f), (cd, cf)| (d + cd, f + cf));
         } else {
-            v = self.get_formats(&self.paths[0])?;
+            entries = self.get_entries(&self.paths[0], self.recursive);
+            (dirs, files) = count(&entries);
+        }
+
+        let mut v = Vec::new();
+        for entry in entries {
+            v.push(Format::try_from(entry)?);
         }
 
-        print!("\x1b[m");
+        print!("{}", reset());
         if list {
-            for e in v {
-                print!("{e:#}");
-            }
-        } else {
-            let (cols, _rows) = crossterm::terminal::size()?;
-            let mut current = 0;
-            let max = v.iter().map(|f| f.v.len()).max().unwrap() + 1;
-            let cap = cols as usize / max;
-            for e in v {
-                if current >= cap {
-                    current = 0;
-                    println!();
+            let style = if self.ascii {
+                &ASCII_TREE
+            } else {
+                &UNICODE_TREE
+            };
+            if !tree {
+                for e in &v {
+                    println!("{e}");
                 }
-                let dif = max - e.v.len();
-                print!("{}{}", e.to_string(), " ".repeat(dif));
-                current += 1;
+            } else if self.paths.len() > 1 {
+                for e in &v {
+                    println!("{e}");
+                    print!(
+                        "{}",
+                        Tree {
+                            fmts: &e.childs,
+                            style
+                        }
+                    );
+                }
+            } else {
+                print!("{}", Tree { fmts: &v, style });
+            }
+
+            if tree {
+                println!();
+                println!(
+                    "{}, {}",
+                    plural(dirs, "directory", "directories"),
+                    plural(files, "file", "files")
+                );
             }
-            println!();
+        } else {
+            let cols = crossterm::terminal::size().map_or(80, |(cols, _)| cols);
+            print_grid(&v, cols as usize, self.across);
         }
 
         return Ok(());
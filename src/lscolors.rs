@@ -0,0 +1,189 @@
+use std::{collections::HashMap, fs::read_to_string, path::Path};
+
+use anyhow::{Result, anyhow};
+use mlua::{Function, Lua, Value};
+
+use crate::{
+    LUA, MAP,
+    util::{ATTRIBUTES, BROKEN_LINK_COLOR, Color, FileType, Style},
+};
+
+fn basic(n: u8) -> Color {
+    match n {
+        0 => Color::BLACK,
+        1 => Color::RED,
+        2 => Color::GREEN,
+        3 => Color::YELLOW,
+        4 => Color::BLUE,
+        5 => Color::MAGENTA,
+        6 => Color::CYAN,
+        _ => Color::WHITE,
+    }
+}
+
+fn bright(n: u8) -> Color {
+    match n {
+        0 => Color::BRIGHT_BLACK,
+        1 => Color::BRIGHT_RED,
+        2 => Color::BRIGHT_GREEN,
+        3 => Color::BRIGHT_YELLOW,
+        4 => Color::BRIGHT_BLUE,
+        5 => Color::BRIGHT_MAGENTA,
+        6 => Color::BRIGHT_CYAN,
+        _ => Color::BRIGHT_WHITE,
+    }
+}
+
+/// the style of a SGR sequence like `01;38;5;208`
+pub fn sgr_style(sgr: &str) -> Style {
+    let codes: Vec<u8> = sgr.split(';').filter_map(|c| c.parse().ok()).collect();
+    let mut style = Style::default();
+    let mut i = 0;
+    while i < codes.len() {
+        match codes[i] {
+            0 => style = Style::default(),
+            39 => style.fg = Color::WHITE,
+            49 => style.bg = None,
+            n @ 30..=37 => style.fg = basic(n - 30),
+            n @ 40..=47 => style.bg = Some(basic(n - 40)),
+            n @ 90..=97 => style.fg = bright(n - 90),
+            n @ 100..=107 => style.bg = Some(bright(n - 100)),
+            n @ (38 | 48) => {
+                let c = match codes.get(i + 1) {
+                    Some(5) => {
+                        let c = codes.get(i + 2).map(|n| Color::PALETTE(*n));
+                        i += 2;
+                        c
+                    }
+                    Some(2) => {
+                        let c = match codes.get(i + 2..i + 5) {
+                            Some([r, g, b]) => Some(Color::RGB(*r, *g, *b)),
+                            _ => None,
+                        };
+                        i += 4;
+                        c
+                    }
+                    _ => None,
+                };
+                match (n, c) {
+                    (38, Some(c)) => style.fg = c,
+                    (_, Some(c)) => style.bg = Some(c),
+                    _ => {}
+                }
+            }
+            n => {
+                if let Some((_, attr, _)) = ATTRIBUTES.iter().find(|(_, _, code)| *code == n) {
+                    style.attrs |= attr;
+                }
+            }
+        }
+        i += 1;
+    }
+    style
+}
+
+/// the registry slot of a LS_COLORS key, `None` for the keys we can't show
+fn file_type(key: &str) -> Option<FileType> {
+    if let Some(ext) = key.strip_prefix("*.") {
+        return Some(FileType::OtherFile(ext.to_string()));
+    }
+    Some(match key {
+        "fi" => FileType::GenericFile,
+        "di" => FileType::GenericDir,
+        "ln" => FileType::GenericSymLink,
+        "ex" => FileType::GenericExecutable,
+        "pi" => FileType::GenericFifo,
+        "so" => FileType::GenericSocket,
+        "bd" => FileType::GenericBlockDevice,
+        "cd" => FileType::GenericCharDevice,
+        _ => return None,
+    })
+}
+
+/// parses `key=sgr:key=sgr:...`
+pub fn parse_ls_colors(s: &str) -> Vec<(String, String)> {
+    s.split(':')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+/// parses a `dircolors --print-database` style file into LS_COLORS pairs
+pub fn parse_dircolors(s: &str) -> Vec<(String, String)> {
+    let keywords = HashMap::from([
+        ("NORMAL", "no"),
+        ("FILE", "fi"),
+        ("DIR", "di"),
+        ("LINK", "ln"),
+        ("SYMLINK", "ln"),
+        ("ORPHAN", "or"),
+        ("FIFO", "pi"),
+        ("PIPE", "pi"),
+        ("SOCK", "so"),
+        ("BLK", "bd"),
+        ("BLOCK", "bd"),
+        ("CHR", "cd"),
+        ("CHAR", "cd"),
+        ("EXEC", "ex"),
+    ]);
+
+    let mut pairs = Vec::new();
+    for line in s.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let (Some(key), Some(sgr)) = (words.next(), words.next()) else {
+            continue;
+        };
+
+        let key = if let Some(ext) = key.strip_prefix('.') {
+            format!("*.{ext}")
+        } else if key.starts_with("*.") {
+            key.to_string()
+        } else if let Some(k) = keywords.get(key.to_uppercase().as_str()) {
+            k.to_string()
+        } else {
+            continue;
+        };
+        pairs.push((key, sgr.to_string()));
+    }
+    pairs
+}
+
+/// a formatter that writes the name in a single style
+fn style_formatter(lua: &Lua, style: Style) -> mlua::Result<Function> {
+    lua.create_function(move |lua, (name, _, _): (String, Value, Value)| {
+        let span = lua.create_table()?;
+        span.push(name)?;
+        span.set("col", style)?;
+        lua.create_sequence_from([span])
+    })
+}
+
+/// fills the registry with the LS_COLORS environment variable and, if given, a
+/// dircolors database. lua configs are loaded after so they take precedence
+pub fn load_ls_colors(dircolors: Option<&Path>) -> Result<()> {
+    let mut pairs = Vec::new();
+    if let Some(path) = dircolors {
+        pairs.extend(parse_dircolors(&read_to_string(path)?));
+    }
+    if let Ok(s) = std::env::var("LS_COLORS") {
+        pairs.extend(parse_ls_colors(&s));
+    }
+
+    let lua = LUA.lock().map_err(|err| anyhow!(err.to_string()))?;
+    let mut map = MAP.lock().map_err(|err| anyhow!(err.to_string()))?;
+    for (key, sgr) in pairs {
+        let style = sgr_style(&sgr);
+        if key == "or" {
+            *BROKEN_LINK_COLOR
+                .lock()
+                .map_err(|err| anyhow!(err.to_string()))? = style.fg;
+            continue;
+        }
+        if let Some(ft) = file_type(&key) {
+            map.insert(ft, style_formatter(&lua, style)?);
+        }
+    }
+
+    Ok(())
+}
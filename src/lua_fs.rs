@@ -0,0 +1,251 @@
+use std::{
+    env::current_dir,
+    ffi::OsStr,
+    fs::{self, File, Metadata},
+    io::{self, Read},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use mlua::{Lua, Table};
+
+use crate::{
+    error::EntryError,
+    glob::{glob_match, has_wildcards},
+    users::{group_name, user_name},
+    util::{entry_type, is_hidden, mode_string},
+};
+
+/// what `fs.read_file` returns at most, a larger `max` is cut down to it
+const READ_FILE_CAP: u64 = 1024 * 1024;
+
+/// paths go through lua as raw bytes so names that aren't utf-8 survive
+fn path_arg(s: &mlua::String) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(&s.as_bytes()))
+}
+
+fn lua_path(lua: &Lua, path: &Path) -> mlua::Result<mlua::String> {
+    lua.create_string(path.as_os_str().as_bytes())
+}
+
+fn access(path: &Path) -> impl FnOnce(io::Error) -> mlua::Error {
+    move |source| {
+        mlua::Error::external(EntryError::Access {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+fn stat_table(lua: &Lua, meta: &Metadata) -> mlua::Result<Table> {
+    let ty = entry_type(meta);
+    let perm = meta.mode() & 0o7777;
+    let t = lua.create_table()?;
+    t.set("type", ty.name())?;
+    t.set("size", meta.size())?;
+    t.set("mode", mode_string(&ty, perm))?;
+    t.set("perm", perm)?;
+    t.set("nlink", meta.nlink())?;
+    t.set("uid", meta.uid())?;
+    t.set("gid", meta.gid())?;
+    t.set("user", user_name(meta.uid()))?;
+    t.set("group", group_name(meta.gid()))?;
+    t.set("mtime", meta.mtime())?;
+    t.set("ctime", meta.ctime())?;
+    t.set("atime", meta.atime())?;
+    t.set("dev", meta.dev())?;
+    t.set("ino", meta.ino())?;
+    Ok(t)
+}
+
+/// `{name, path, type}` of every entry, hidden ones included, sorted by name
+fn read_dir(lua: &Lua, path: mlua::String) -> mlua::Result<Table> {
+    let path = path_arg(&path);
+    let dir = fs::read_dir(&path).map_err(|source| {
+        mlua::Error::external(EntryError::OpenDir {
+            path: path.clone(),
+            source,
+        })
+    })?;
+
+    let mut entries = Vec::new();
+    for entry in dir {
+        let entry = entry.map_err(|source| {
+            mlua::Error::external(EntryError::ReadDir {
+                path: path.clone(),
+                source,
+            })
+        })?;
+        entries.push(entry);
+    }
+    entries.sort_by_key(|e| e.file_name());
+
+    let list = lua.create_table()?;
+    for entry in entries {
+        let t = lua.create_table()?;
+        t.set("name", lua.create_string(entry.file_name().as_bytes())?)?;
+        t.set("path", lua_path(lua, &entry.path())?)?;
+        // nil when it vanished since the directory was read
+        t.set("type", entry.metadata().ok().map(|m| entry_type(&m).name()))?;
+        list.push(t)?;
+    }
+    Ok(list)
+}
+
+fn stat(lua: &Lua, path: mlua::String) -> mlua::Result<Table> {
+    let path = path_arg(&path);
+    stat_table(lua, &fs::metadata(&path).map_err(access(&path))?)
+}
+
+fn lstat(lua: &Lua, path: mlua::String) -> mlua::Result<Table> {
+    let path = path_arg(&path);
+    stat_table(lua, &fs::symlink_metadata(&path).map_err(access(&path))?)
+}
+
+fn exists(_: &Lua, path: mlua::String) -> mlua::Result<bool> {
+    Ok(path_arg(&path).exists())
+}
+
+fn is_dir(_: &Lua, path: mlua::String) -> mlua::Result<bool> {
+    Ok(path_arg(&path).is_dir())
+}
+
+/// the first `max` bytes of the file, never more than a megabyte
+fn read_file(lua: &Lua, (path, max): (mlua::String, Option<u64>)) -> mlua::Result<mlua::String> {
+    let path = path_arg(&path);
+    let file = File::open(&path).map_err(access(&path))?;
+    let mut buf = Vec::new();
+    file.take(max.unwrap_or(READ_FILE_CAP).min(READ_FILE_CAP))
+        .read_to_end(&mut buf)
+        .map_err(access(&path))?;
+    lua.create_string(buf)
+}
+
+fn realpath(lua: &Lua, path: mlua::String) -> mlua::Result<mlua::String> {
+    let path = path_arg(&path);
+    lua_path(lua, &fs::canonicalize(&path).map_err(access(&path))?)
+}
+
+fn readlink(lua: &Lua, path: mlua::String) -> mlua::Result<mlua::String> {
+    let path = path_arg(&path);
+    let target = fs::read_link(&path).map_err(|source| {
+        mlua::Error::external(EntryError::ReadLink {
+            path: path.clone(),
+            source,
+        })
+    })?;
+    lua_path(lua, &target)
+}
+
+/// `base` and every directory below it, symlinks and hidden ones excluded
+fn walk_dirs(base: &Path, out: &mut Vec<PathBuf>) {
+    out.push(base.to_path_buf());
+    let dir = if base.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        base
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        if !is_hidden(&name) && entry.file_type().is_ok_and(|t| t.is_dir()) {
+            walk_dirs(&base.join(name), out);
+        }
+    }
+}
+
+/// expands a pattern one component at a time, `**` stands for any number of
+/// directories. hidden names only match a pattern starting with a dot
+fn expand(pattern: &str) -> Vec<PathBuf> {
+    let (mut found, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec![PathBuf::from("/")], rest),
+        None => (vec![PathBuf::new()], pattern),
+    };
+
+    for part in rest.split('/').filter(|p| !p.is_empty()) {
+        let mut next = Vec::new();
+        for base in &found {
+            if part == "**" {
+                walk_dirs(base, &mut next);
+            } else if !has_wildcards(part) {
+                next.push(base.join(part));
+            } else {
+                let dir = if base.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    base
+                };
+                let Ok(entries) = fs::read_dir(dir) else {
+                    continue;
+                };
+                for entry in entries.flatten() {
+                    let name = entry.file_name();
+                    if is_hidden(&name) && !part.starts_with('.') {
+                        continue;
+                    }
+                    if glob_match(part, &name.to_string_lossy()) {
+                        next.push(base.join(name));
+                    }
+                }
+            }
+        }
+        found = next;
+    }
+
+    found.retain(|p| !p.as_os_str().is_empty() && fs::symlink_metadata(p).is_ok());
+    found.sort();
+    found.dedup();
+    found
+}
+
+fn glob(lua: &Lua, pattern: String) -> mlua::Result<Table> {
+    let list = lua.create_table()?;
+    for path in expand(&pattern) {
+        list.push(lua_path(lua, &path)?)?;
+    }
+    Ok(list)
+}
+
+/// the path of the first `marker` found in `start` or one of its parents,
+/// nil if there is none. starts from the current directory by default
+fn find_up(
+    lua: &Lua,
+    (marker, start): (mlua::String, Option<mlua::String>),
+) -> mlua::Result<Option<mlua::String>> {
+    let marker = path_arg(&marker);
+    let start = match start {
+        Some(start) => path_arg(&start),
+        None => PathBuf::new(),
+    };
+    let start = if start.is_absolute() {
+        start
+    } else {
+        current_dir()?.join(start)
+    };
+
+    for dir in start.ancestors() {
+        let candidate = dir.join(&marker);
+        if fs::symlink_metadata(&candidate).is_ok() {
+            return Ok(Some(lua_path(lua, &candidate)?));
+        }
+    }
+    Ok(None)
+}
+
+/// the `fs` global of the lua configs
+pub fn create_fs(lua: &Lua) -> mlua::Result<Table> {
+    let fs = lua.create_table()?;
+    fs.set("read_dir", lua.create_function(read_dir)?)?;
+    fs.set("stat", lua.create_function(stat)?)?;
+    fs.set("lstat", lua.create_function(lstat)?)?;
+    fs.set("exists", lua.create_function(exists)?)?;
+    fs.set("is_dir", lua.create_function(is_dir)?)?;
+    fs.set("read_file", lua.create_function(read_file)?)?;
+    fs.set("glob", lua.create_function(glob)?)?;
+    fs.set("realpath", lua.create_function(realpath)?)?;
+    fs.set("readlink", lua.create_function(readlink)?)?;
+    fs.set("find_up", lua.create_function(find_up)?)?;
+    Ok(fs)
+}
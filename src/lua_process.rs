@@ -0,0 +1,222 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    os::unix::process::{CommandExt, ExitStatusExt},
+    path::PathBuf,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{LazyLock, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use mlua::{Lua, Table};
+
+/// how often a child with a timeout is checked on
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// what a process is started with, also the key of the cache
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Request {
+    pub cmd: String,
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    pub stdin: Option<Vec<u8>>,
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// `None` when the process was killed by a signal
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+    pub timed_out: bool,
+}
+
+fn drain(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut buf)?;
+        }
+        Ok(buf)
+    })
+}
+
+/// kills the child and whatever it started, they are in the group made by
+/// `process_group(0)` whose id is the child's
+fn kill_group(child: &Child) {
+    // SAFETY: kill only sends a signal, a group that is already gone is ESRCH
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/// waits for the child and for its pipes to be closed, killing its process
+/// group once the timeout is over. A process the child started in the
+/// background can keep the pipes open after the child itself exited
+fn wait(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    readers: &[&JoinHandle<io::Result<Vec<u8>>>],
+) -> io::Result<(ExitStatus, bool)> {
+    let Some(timeout) = timeout else {
+        return Ok((child.wait()?, false));
+    };
+
+    let deadline = Instant::now() + timeout;
+    let mut status = None;
+    loop {
+        if status.is_none() {
+            status = child.try_wait()?;
+        }
+        if let Some(status) = status
+            && readers.iter().all(|r| r.is_finished())
+        {
+            return Ok((status, false));
+        }
+        if Instant::now() >= deadline {
+            kill_group(child);
+            let status = match status {
+                Some(status) => status,
+                None => child.wait()?,
+            };
+            return Ok((status, true));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// runs the command without a shell. stdin is written and both pipes are read
+/// on their own threads so a child filling one of them never blocks
+pub fn run(req: &Request) -> io::Result<Outcome> {
+    let mut cmd = Command::new(&req.cmd);
+    cmd.args(&req.args)
+        .envs(req.env.iter().map(|(k, v)| (k, v)))
+        .stdin(if req.stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(cwd) = &req.cwd {
+        cmd.current_dir(cwd);
+    }
+    // its own group, so a timeout kills what it started too
+    cmd.process_group(0);
+
+    let mut child = cmd.spawn()?;
+    let writer = match (child.stdin.take(), req.stdin.clone()) {
+        (Some(mut pipe), Some(input)) => Some(thread::spawn(move || {
+            // a child that doesn't read all of its input is not an error
+            let _ = pipe.write_all(&input);
+        })),
+        _ => None,
+    };
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let (status, timed_out) = wait(&mut child, req.timeout, &[&stdout, &stderr])?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    let join = |h: JoinHandle<io::Result<Vec<u8>>>| {
+        h.join()
+            .unwrap_or_else(|_| Err(io::Error::other("reader thread panicked")))
+    };
+
+    Ok(Outcome {
+        stdout: join(stdout)?,
+        stderr: join(stderr)?,
+        code: status.code(),
+        signal: status.signal(),
+        timed_out,
+    })
+}
+
+/// finished runs by the `file:line` of the lua call and the request, so a
+/// formatter called once per entry only starts each distinct command once
+static CACHE: LazyLock<Mutex<HashMap<(String, Request), Outcome>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// forgets every run, the next calls start their commands again
+pub fn clear_cache() {
+    if let Ok(mut c) = CACHE.lock() {
+        c.clear();
+    }
+}
+
+fn call_site(lua: &Lua) -> String {
+    match lua.inspect_stack(1) {
+        Some(debug) => {
+            let src = debug.source().short_src.unwrap_or_default().into_owned();
+            format!("{src}:{}", debug.curr_line())
+        }
+        None => String::new(),
+    }
+}
+
+fn request(t: &Table) -> mlua::Result<Request> {
+    let mut env: Vec<(String, String)> = match t.get::<Option<Table>>("env")? {
+        Some(env) => env.pairs().collect::<mlua::Result<_>>()?,
+        None => Vec::new(),
+    };
+    env.sort();
+
+    Ok(Request {
+        cmd: t.get("cmd")?,
+        args: t.get::<Option<Vec<String>>>("args")?.unwrap_or_default(),
+        cwd: t.get::<Option<String>>("cwd")?.map(PathBuf::from),
+        env,
+        stdin: t
+            .get::<Option<mlua::String>>("stdin")?
+            .map(|s| s.as_bytes().to_vec()),
+        timeout: t
+            .get::<Option<u64>>("timeout_ms")?
+            .map(Duration::from_millis),
+    })
+}
+
+fn outcome_table(lua: &Lua, out: &Outcome) -> mlua::Result<Table> {
+    let t = lua.create_table()?;
+    t.set("stdout", lua.create_string(&out.stdout)?)?;
+    t.set("stderr", lua.create_string(&out.stderr)?)?;
+    t.set("code", out.code)?;
+    t.set("signal", out.signal)?;
+    t.set("timed_out", out.timed_out)?;
+    t.set("ok", out.code == Some(0))?;
+    Ok(t)
+}
+
+/// `process.run{cmd=..., args=..., cwd=..., env=..., stdin=..., timeout_ms=...}`,
+/// `cache=false` always starts the process
+fn lua_run(lua: &Lua, t: Table) -> mlua::Result<Table> {
+    let req = request(&t)?;
+    let cache = t.get::<Option<bool>>("cache")?.unwrap_or(true);
+    let key = (call_site(lua), req);
+
+    if cache && let Some(out) = CACHE.lock().ok().and_then(|c| c.get(&key).cloned()) {
+        return outcome_table(lua, &out);
+    }
+
+    let out = run(&key.1)
+        .map_err(|err| mlua::Error::RuntimeError(format!("cannot run '{}': {err}", key.1.cmd)))?;
+    // a timeout may not happen again, it is not worth remembering
+    if cache
+        && !out.timed_out
+        && let Ok(mut c) = CACHE.lock()
+    {
+        c.insert(key, out.clone());
+    }
+    outcome_table(lua, &out)
+}
+
+/// the `process` global of the lua configs
+pub fn create_process(lua: &Lua) -> mlua::Result<Table> {
+    let process = lua.create_table()?;
+    process.set("run", lua.create_function(lua_run)?)?;
+    Ok(process)
+}
@@ -0,0 +1,347 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    path::Path,
+    sync::{LazyLock, Mutex},
+    time::SystemTime,
+};
+
+/// how much of a file is read to guess what it is
+const HEAD_LEN: u64 = 512;
+
+/// (device, inode, modification time), a file is only read again once changed
+pub type FileId = (u64, u64, SystemTime);
+
+static CACHE: LazyLock<Mutex<HashMap<FileId, Option<String>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// signatures checked before the shared-mime-info database, (offset, bytes, mime)
+const SIGNATURES: [(usize, &[u8], &str); 13] = [
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"PK\x05\x06", "application/zip"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"\xfd7zXZ\x00", "application/x-xz"),
+    (0, b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (0, b"SQLite format 3\x00", "application/vnd.sqlite3"),
+    (0, b"MZ", "application/x-msdownload"),
+    (257, b"ustar", "application/x-tar"),
+];
+
+/// reads an unsigned integer of `len` bytes in the byte order of the ELF file
+fn elf_uint(head: &[u8], at: usize, len: usize, big_endian: bool) -> Option<u64> {
+    let bytes = head.get(at..at.checked_add(len)?)?;
+    let fold = |n: u64, b: &u8| n << 8 | *b as u64;
+    Some(if big_endian {
+        bytes.iter().fold(0, fold)
+    } else {
+        bytes.iter().rev().fold(0, fold)
+    })
+}
+
+/// position independent executables and shared libraries are both `ET_DYN`,
+/// only executables ask for an interpreter
+fn elf_has_interpreter(head: &[u8], big_endian: bool) -> Option<bool> {
+    const PT_INTERP: u64 = 3;
+    let (phoff, phentsize, phnum) = match head.get(4)? {
+        2 => (
+            elf_uint(head, 32, 8, big_endian)?,
+            elf_uint(head, 54, 2, big_endian)?,
+            elf_uint(head, 56, 2, big_endian)?,
+        ),
+        _ => (
+            elf_uint(head, 28, 4, big_endian)?,
+            elf_uint(head, 42, 2, big_endian)?,
+            elf_uint(head, 44, 2, big_endian)?,
+        ),
+    };
+    // the header comes from the file, nothing in it can be trusted
+    for i in 0..phnum {
+        let at = phentsize
+            .checked_mul(i)
+            .and_then(|o| o.checked_add(phoff))?;
+        let at = usize::try_from(at).ok()?;
+        if at >= head.len() {
+            break;
+        }
+        if elf_uint(head, at, 4, big_endian)? == PT_INTERP {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+fn elf(head: &[u8]) -> &'static str {
+    let big_endian = head.get(5) == Some(&2);
+    match elf_uint(head, 16, 2, big_endian) {
+        Some(1) => "application/x-object",
+        Some(3) if elf_has_interpreter(head, big_endian) == Some(true) => {
+            "application/x-pie-executable"
+        }
+        Some(3) => "application/x-sharedlib",
+        Some(4) => "application/x-core",
+        _ => "application/x-executable",
+    }
+}
+
+/// the interpreter of a `#!` line, the one `env` runs if it is `env`
+fn interpreter(head: &[u8]) -> Option<String> {
+    let line = head.strip_prefix(b"#!")?;
+    let line = line.split(|b| *b == b'\n').next()?;
+    let line = String::from_utf8_lossy(line);
+    let mut words = line.split_whitespace();
+
+    let mut prog = words.next()?.rsplit('/').next()?.to_string();
+    if prog == "env" {
+        // `env -S prog args` and the like
+        prog = words.find(|w| !w.starts_with('-'))?.to_string();
+    }
+    Some(prog)
+}
+
+fn script_mime(interpreter: &str) -> &'static str {
+    let prog = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    match prog {
+        "sh" | "bash" | "dash" | "zsh" | "ksh" | "mksh" | "ash" => "application/x-shellscript",
+        "python" if interpreter.starts_with("python3") => "text/x-python3",
+        "python" => "text/x-python",
+        "perl" => "application/x-perl",
+        "ruby" => "application/x-ruby",
+        "node" | "nodejs" | "deno" | "bun" => "application/javascript",
+        "lua" | "luajit" => "text/x-lua",
+        "php" => "application/x-php",
+        "awk" | "gawk" | "mawk" => "application/x-awk",
+        "tclsh" | "wish" => "text/x-tcl",
+        "fish" => "application/x-fishscript",
+        _ => "text/x-script",
+    }
+}
+
+/// a rule of the shared-mime-info magic file, see its specification
+struct MagicRule {
+    indent: usize,
+    offset: usize,
+    value: Vec<u8>,
+    mask: Option<Vec<u8>>,
+    range: usize,
+}
+
+impl MagicRule {
+    fn matches(&self, head: &[u8]) -> bool {
+        (self.offset..self.offset + self.range).any(|start| {
+            let Some(window) = head.get(start..start + self.value.len()) else {
+                return false;
+            };
+            match &self.mask {
+                Some(mask) => window
+                    .iter()
+                    .zip(mask)
+                    .zip(&self.value)
+                    .all(|((b, m), v)| b & m == v & m),
+                None => window == self.value,
+            }
+        })
+    }
+}
+
+struct MagicSection {
+    mime: String,
+    rules: Vec<MagicRule>,
+}
+
+impl MagicSection {
+    /// a rule matches when its value does and, if it has nested rules, one
+    /// of them matches too
+    fn matches_from(&self, i: usize, head: &[u8]) -> bool {
+        let rule = &self.rules[i];
+        if !rule.matches(head) {
+            return false;
+        }
+        let mut childs = self.rules[i + 1..]
+            .iter()
+            .enumerate()
+            .take_while(|(_, r)| r.indent > rule.indent)
+            .filter(|(_, r)| r.indent == rule.indent + 1)
+            .peekable();
+        if childs.peek().is_none() {
+            return true;
+        }
+        childs.any(|(j, _)| self.matches_from(i + 1 + j, head))
+    }
+
+    fn matches(&self, head: &[u8]) -> bool {
+        (0..self.rules.len()).any(|i| self.rules[i].indent == 0 && self.matches_from(i, head))
+    }
+}
+
+fn number(buf: &[u8], i: &mut usize) -> Option<usize> {
+    let start = *i;
+    while buf.get(*i).is_some_and(u8::is_ascii_digit) {
+        *i += 1;
+    }
+    std::str::from_utf8(&buf[start..*i]).ok()?.parse().ok()
+}
+
+/// `[indent]>offset=<u16 length><value>[&mask][~word size][+range]\n`
+fn parse_rule(buf: &[u8], i: &mut usize) -> Option<MagicRule> {
+    let indent = number(buf, i).unwrap_or(0);
+    (buf.get(*i) == Some(&b'>')).then_some(())?;
+    *i += 1;
+    let offset = number(buf, i)?;
+    (buf.get(*i) == Some(&b'=')).then_some(())?;
+    *i += 1;
+
+    let len = u16::from_be_bytes([*buf.get(*i)?, *buf.get(*i + 1)?]) as usize;
+    *i += 2;
+    let value = buf.get(*i..*i + len)?.to_vec();
+    *i += len;
+
+    let mut mask = None;
+    let mut range = 1;
+    loop {
+        match buf.get(*i)? {
+            b'&' => {
+                mask = Some(buf.get(*i + 1..*i + 1 + len)?.to_vec());
+                *i += 1 + len;
+            }
+            b'~' => {
+                // the word size only matters for byte swapping, values are
+                // compared as they are stored
+                *i += 1;
+                number(buf, i)?;
+            }
+            b'+' => {
+                *i += 1;
+                range = number(buf, i)?;
+            }
+            b'\n' => {
+                *i += 1;
+                break;
+            }
+            _ => return None,
+        }
+    }
+
+    Some(MagicRule {
+        indent,
+        offset,
+        value,
+        mask,
+        range,
+    })
+}
+
+fn parse_magic(buf: &[u8]) -> Vec<(u32, MagicSection)> {
+    let mut sections = Vec::new();
+    let Some(mut rest) = buf.strip_prefix(b"MIME-Magic\0\n") else {
+        return sections;
+    };
+
+    while let Some(body) = rest.strip_prefix(b"[") {
+        let Some(end) = body.iter().position(|b| *b == b'\n') else {
+            break;
+        };
+        let header = String::from_utf8_lossy(&body[..end]);
+        let Some((priority, mime)) = header.trim_end_matches(']').split_once(':') else {
+            break;
+        };
+
+        let mut i = end + 1;
+        let mut rules = Vec::new();
+        while i < body.len() && body[i] != b'[' {
+            match parse_rule(body, &mut i) {
+                Some(rule) => rules.push(rule),
+                // a line we don't understand, the rest of the file can't be trusted
+                None => return sections,
+            }
+        }
+
+        sections.push((
+            priority.parse().unwrap_or(50),
+            MagicSection {
+                mime: mime.to_string(),
+                rules,
+            },
+        ));
+        rest = &body[i..];
+    }
+
+    sections.sort_by_key(|(priority, _)| std::cmp::Reverse(*priority));
+    sections
+}
+
+/// the shared-mime-info magic databases of the XDG data directories
+static MAGIC: LazyLock<Vec<(u32, MagicSection)>> = LazyLock::new(|| {
+    let dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or("/usr/local/share:/usr/share".to_string());
+
+    let mut sections = Vec::new();
+    for dir in dirs.split(':') {
+        if let Ok(buf) = std::fs::read(Path::new(dir).join("mime/magic")) {
+            sections.extend(parse_magic(&buf));
+        }
+    }
+    sections.sort_by_key(|(priority, _)| std::cmp::Reverse(*priority));
+    sections
+});
+
+/// the MIME type of the first bytes of a file
+pub fn detect(head: &[u8]) -> String {
+    if head.is_empty() {
+        return "application/x-zerosize".to_string();
+    }
+    if head.starts_with(b"\x7fELF") {
+        return elf(head).to_string();
+    }
+    if let Some(prog) = interpreter(head) {
+        return script_mime(&prog).to_string();
+    }
+    for (offset, magic, mime) in SIGNATURES {
+        if head.get(offset..).is_some_and(|h| h.starts_with(magic)) {
+            return mime.to_string();
+        }
+    }
+    if let Some((_, section)) = MAGIC.iter().find(|(_, s)| s.matches(head)) {
+        return section.mime.clone();
+    }
+
+    // what shared-mime-info falls back to as well
+    if is_text(head) {
+        "text/plain".to_string()
+    } else {
+        "application/octet-stream".to_string()
+    }
+}
+
+fn is_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // a character cut by the end of what was read
+        Err(err) => err.error_len().is_none(),
+    }
+}
+
+/// the MIME type of a regular file, `None` if it can't be read
+pub fn mime_type(path: &Path, id: FileId) -> Option<String> {
+    if let Some(mime) = CACHE.lock().ok()?.get(&id) {
+        return mime.clone();
+    }
+
+    let mut head = Vec::new();
+    let mime = File::open(path)
+        .and_then(|f| f.take(HEAD_LEN).read_to_end(&mut head))
+        .ok()
+        .map(|_| detect(&head));
+    CACHE.lock().ok()?.insert(id, mime.clone());
+    mime
+}
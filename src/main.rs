@@ -1,12 +1,31 @@
 mod api;
+mod error;
 mod explorer;
+mod fileops;
 mod fmt;
+mod glob;
 mod list;
+mod lscolors;
+mod lua_fs;
+mod lua_process;
+mod magic;
+mod meta;
+mod output;
+mod preview;
+mod quote;
+mod rules;
+mod sort;
+mod users;
 mod util;
 
-use api::{bash, get_formats};
-use explorer::Explorer;
+use api::{bash, get_formats, get_layout};
+use error::take_errors;
+use explorer::{Explorer, ExplorerArgs};
 use list::List;
+use lscolors::load_ls_colors;
+use lua_fs::create_fs;
+use lua_process::create_process;
+use quote::QuotingStyle;
 use util::*;
 
 #[allow(deprecated)]
@@ -42,10 +61,36 @@ pub static MAP: LazyLock<Mutex<HashMap<FileType, mlua::Function>>> = LazyLock::n
         .eval()
         .unwrap();
 
+    let def_exec: Function = lua
+        .load("function(name, path, tick) return '󰆍 ' .. name .. '*' end")
+        .eval()
+        .unwrap();
+    let def_fifo: Function = lua
+        .load("function(name, path, tick) return '󰟥 ' .. name .. '|' end")
+        .eval()
+        .unwrap();
+    let def_socket: Function = lua
+        .load("function(name, path, tick) return '󰆨 ' .. name .. '=' end")
+        .eval()
+        .unwrap();
+    let def_block: Function = lua
+        .load("function(name, path, tick) return '󰋊 ' .. name end")
+        .eval()
+        .unwrap();
+    let def_char: Function = lua
+        .load("function(name, path, tick) return '󰘳 ' .. name end")
+        .eval()
+        .unwrap();
+
     return Mutex::new(HashMap::from([
         (FT::GenericFile, def_file),
         (FT::GenericDir, def_dir),
         (FT::GenericSymLink, def_link),
+        (FT::GenericExecutable, def_exec),
+        (FT::GenericFifo, def_fifo),
+        (FT::GenericSocket, def_socket),
+        (FT::GenericBlockDevice, def_block),
+        (FT::GenericCharDevice, def_char),
     ]));
 });
 
@@ -83,7 +128,7 @@ fn curr_dir() -> PathBuf {
 #[derive(Parser)]
 enum Mode {
     List(List),
-    Explorer,
+    Explorer(ExplorerArgs),
 }
 
 #[derive(Parser)]
@@ -101,9 +146,29 @@ pub struct Options {
     #[arg(long, short, default_value_t = false)]
     verbose: bool,
 
-    #[arg(value_enum, long, short, default_value_t=SortBy::Name)]
+    #[arg(value_enum, long, short, global = true, default_value_t=SortBy::Name)]
     sort_by: SortBy,
 
+    /// reverse the sorting order
+    #[arg(long, global = true, default_value_t = false)]
+    reverse: bool,
+
+    /// list directories before anything else
+    #[arg(long, global = true, default_value_t = false)]
+    dirs_first: bool,
+
+    /// when to use colors
+    #[arg(value_enum, long, global = true, default_value_t = ColorMode::Auto)]
+    color: ColorMode,
+
+    /// dircolors database loaded before LS_COLORS and the lua config
+    #[arg(long, global = true)]
+    dircolors: Option<PathBuf>,
+
+    /// how file names are written
+    #[arg(value_enum, long, global = true, default_value_t = QuotingStyle::Literal)]
+    quoting_style: QuotingStyle,
+
     #[command(subcommand)]
     mode: Mode,
 }
@@ -114,38 +179,66 @@ pub fn get_options<'a>() -> &'a Options {
     OPTIONS.get_or_init(Options::parse)
 }
 
-fn init_lua() -> Result<()> {
-    let lua = LUA.lock().map_err(|err| anyhow!(err.to_string()))?;
-    let path = &get_options().config;
-
-    let load_format_function = lua.create_function(get_formats)?;
-    lua.globals().set("load_formats", load_format_function)?;
+/// what every lua state has whatever it is used for
+pub fn init_globals(lua: &Lua) -> Result<()> {
     let bash_function = lua.create_function(bash)?;
     lua.globals().set("bash", bash_function)?;
+    lua.globals().set("fs", create_fs(lua)?)?;
+    lua.globals().set("process", create_process(lua)?)?;
+    Ok(())
+}
 
-    if let Ok(mut file) = File::open(path) {
+/// runs the config in the state, a missing one is no error
+pub fn run_config(lua: &Lua) -> Result<()> {
+    if let Ok(mut file) = File::open(&get_options().config) {
         let mut buf = String::new();
         file.read_to_string(&mut buf)?;
         lua.load(&buf).exec()?;
     }
-
     Ok(())
 }
 
+fn init_lua() -> Result<()> {
+    let lua = LUA.lock().map_err(|err| anyhow!(err.to_string()))?;
+
+    init_globals(&lua)?;
+    let load_format_function = lua.create_function(get_formats)?;
+    lua.globals().set("load_formats", load_format_function)?;
+    lua.globals()
+        .set("load_layout", lua.create_function(get_layout)?)?;
+    // previewers live in the preview worker's own state
+    lua.globals().set(
+        "load_previews",
+        lua.create_function(|_, _: mlua::Value| Ok(()))?,
+    )?;
+    run_config(&lua)
+}
+
 fn setup_lua() {
     let _init_map = MAP.lock();
 }
 
 fn main() -> Result<()> {
-    print!("\x1b[m");
     OPTIONS.get_or_init(Options::parse);
 
     setup_lua();
+    load_ls_colors(get_options().dircolors.as_deref())?;
     init_lua()?;
     match &get_options().mode {
-        Mode::List(ls) => ls.ls()?, //print_data()? ,
-        Mode::Explorer => {
-            Explorer::new().render()?;
+        Mode::List(ls) => {
+            ls.ls()?;
+            // like ls, everything that could be listed is and the failures
+            // are reported at the end
+            let errors = take_errors();
+            for err in &errors {
+                eprintln!("explorer: {err}");
+            }
+            if !errors.is_empty() {
+                exit(1);
+            }
+        }
+        Mode::Explorer(args) => {
+            Explorer::new(args.clone()).render()?;
         }
     }
 
@@ -0,0 +1,128 @@
+use std::{fs, path::PathBuf, time::SystemTime};
+
+use mlua::{UserData, UserDataFields, UserDataMethods};
+
+use crate::{
+    magic::mime_type,
+    users::{group_name, user_name},
+    util::{
+        Entry, EntryType, Format, ReadOptions, formatter, is_hidden, mode_string, process_path,
+        unix_time,
+    },
+};
+
+/// the fourth argument of the lua formatters. only what is cheap to copy is
+/// kept, every field is worked out when the formatter reads it
+pub struct EntryMeta {
+    path: PathBuf,
+    ty: EntryType,
+    target: Option<PathBuf>,
+    broken: bool,
+    bits: u32,
+    dev: u64,
+    ino: u64,
+    nlink: u64,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    mtime: SystemTime,
+    ctime: SystemTime,
+    atime: SystemTime,
+    depth: usize,
+}
+
+impl EntryMeta {
+    pub fn new(entry: &Entry, depth: usize) -> Self {
+        EntryMeta {
+            path: entry.path.clone(),
+            ty: entry.ty,
+            target: entry.target.clone(),
+            broken: entry.broken,
+            bits: entry.mode_bits(),
+            dev: entry.dev,
+            ino: entry.ino,
+            nlink: entry.nlink,
+            uid: entry.uid,
+            gid: entry.gid,
+            size: entry.size,
+            mtime: entry.mtime,
+            ctime: entry.ctime,
+            atime: entry.atime,
+            depth,
+        }
+    }
+}
+
+impl UserData for EntryMeta {
+    fn add_fields<F: UserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("type", |_, m| Ok(m.ty.name()));
+        fields.add_field_method_get("size", |_, m| Ok(m.size));
+        fields.add_field_method_get("mode", |_, m| Ok(mode_string(&m.ty, m.bits)));
+        fields.add_field_method_get("perm", |_, m| Ok(m.bits));
+        fields.add_field_method_get("nlink", |_, m| Ok(m.nlink));
+        fields.add_field_method_get("uid", |_, m| Ok(m.uid));
+        fields.add_field_method_get("gid", |_, m| Ok(m.gid));
+        fields.add_field_method_get("user", |_, m| Ok(user_name(m.uid)));
+        fields.add_field_method_get("group", |_, m| Ok(group_name(m.gid)));
+        fields.add_field_method_get("mtime", |_, m| Ok(unix_time(m.mtime)));
+        fields.add_field_method_get("ctime", |_, m| Ok(unix_time(m.ctime)));
+        fields.add_field_method_get("atime", |_, m| Ok(unix_time(m.atime)));
+        fields.add_field_method_get("target", |_, m| {
+            Ok(m.target.as_ref().map(|t| t.to_string_lossy().into_owned()))
+        });
+        fields.add_field_method_get("broken", |_, m| Ok(m.broken));
+        fields.add_field_method_get("extension", |_, m| {
+            Ok(m.path.extension().map(|e| e.to_string_lossy().into_owned()))
+        });
+        fields.add_field_method_get("hidden", |_, m| {
+            Ok(m.path.file_name().is_some_and(is_hidden))
+        });
+        fields.add_field_method_get("depth", |_, m| Ok(m.depth));
+        // found from the content, nil for everything but regular files
+        fields.add_field_method_get("mime", |_, m| {
+            if !matches!(m.ty, EntryType::File | EntryType::Executable) {
+                return Ok(None);
+            }
+            Ok(mime_type(&m.path, (m.dev, m.ino, m.mtime)))
+        });
+        // hidden entries included, nil when it can't be read
+        fields.add_field_method_get("children", |_, m| {
+            if m.ty != EntryType::Dir {
+                return Ok(None);
+            }
+            Ok(fs::read_dir(&m.path).ok().map(|dir| dir.count()))
+        });
+    }
+
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        // what the formatter of a symlink's target returns for `name`, the
+        // target's own name by default. nil when the link is dangling. The
+        // formatters live in the main state and previewers call this from
+        // theirs, so only rust values go in and out of the formatter
+        methods.add_method("format_target", |_, m, name: Option<String>| {
+            let Some(target) = &m.target else {
+                return Ok(None);
+            };
+            // relative targets are relative to the directory of the link, the
+            // whole chain is followed so the last name picks the format
+            let target = match m.path.parent() {
+                Some(dir) => dir.join(target),
+                None => target.clone(),
+            };
+            let Ok(target) = fs::canonicalize(target) else {
+                return Ok(None);
+            };
+            let opts = ReadOptions {
+                hidden: true,
+                follow: true,
+            };
+            let Ok(entry) = process_path(target, &opts, 0) else {
+                return Ok(None);
+            };
+
+            let name = name.unwrap_or_else(|| entry.display_name());
+            let meta = EntryMeta::new(&entry, m.depth);
+            formatter(&entry).call::<Option<Format>>((name, entry.path.as_path(), 0, meta))
+        });
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    io::{self, BufWriter, Write},
+    os::unix::fs::MetadataExt,
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use clap::ValueEnum;
+
+use crate::{
+    error::report,
+    sort::sort_entries,
+    util::{Entry, EntryType, Format, ReadOptions, process_path, read_dir, unix_time},
+};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// a single array, directories carry their `children`
+    Json,
+    /// one object per line, written while the tree is walked
+    Ndjson,
+    /// one row per entry with a header line
+    Csv,
+}
+
+const CSV_HEADER: &str =
+    "name,path,type,size,mode,perm,nlink,uid,gid,user,group,mtime,ctime,atime,broken,target,depth";
+
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn csv_str(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn target(entry: &Entry) -> Option<String> {
+    let target = entry.target.as_ref()?;
+    Some(target.display().to_string())
+}
+
+/// every field shared by all the formats, in the csv header order
+fn fields(entry: &Entry) -> Vec<(&'static str, String, bool)> {
+    // (key, value, is the value a string)
+    vec![
+        ("name", entry.name.clone(), true),
+        ("path", entry.path.display().to_string(), true),
+        ("type", entry.ty.name().to_string(), true),
+        ("size", entry.size.to_string(), false),
+        ("mode", entry.mode_string(), true),
+        ("perm", format!("{:04o}", entry.mode_bits()), true),
+        ("nlink", entry.nlink.to_string(), false),
+        ("uid", entry.uid.to_string(), false),
+        ("gid", entry.gid.to_string(), false),
+        ("user", entry.user.clone(), true),
+        ("group", entry.group.clone(), true),
+        ("mtime", unix_time(entry.mtime).to_string(), false),
+        ("ctime", unix_time(entry.ctime).to_string(), false),
+        ("atime", unix_time(entry.atime).to_string(), false),
+        ("broken", entry.broken.to_string(), false),
+    ]
+}
+
+pub struct Output {
+    pub format: OutputFormat,
+    pub opts: ReadOptions,
+    /// also run the lua formatter and add its text as `rendered`
+    pub rendered: bool,
+}
+
+impl Output {
+    fn json_object(&self, entry: &Entry, level: usize) -> Result<String> {
+        let mut obj = String::from("{");
+        for (key, value, string) in fields(entry) {
+            let value = if string { json_str(&value) } else { value };
+            obj.push_str(&format!("{}:{value},", json_str(key)));
+        }
+
+        let target = target(entry).map_or("null".to_string(), |t| json_str(&t));
+        obj.push_str(&format!("\"target\":{target}"));
+        // the json nesting already tells the depth
+        if self.format == OutputFormat::Ndjson {
+            obj.push_str(&format!(",\"depth\":{level}"));
+        }
+        if self.rendered {
+            let text = Format::from_entry(entry, level)?.text();
+            obj.push_str(&format!(",\"rendered\":{}", json_str(&text)));
+        }
+
+        Ok(obj)
+    }
+
+    fn csv_row(&self, entry: &Entry, level: usize) -> Result<String> {
+        let mut row: Vec<String> = fields(entry)
+            .into_iter()
+            .map(|(_, value, _)| csv_str(&value))
+            .collect();
+        row.push(csv_str(&target(entry).unwrap_or_default()));
+        row.push(level.to_string());
+        if self.rendered {
+            row.push(csv_str(&Format::from_entry(entry, level)?.text()));
+        }
+
+        Ok(row.join(","))
+    }
+
+    /// the childs of a directory, read one level at a time so only the
+    /// current branch of the tree is ever in memory
+    fn children(&self, entry: &Entry, depth: u64, ancestors: &[(u64, u64)]) -> Option<Vec<Entry>> {
+        if entry.ty != EntryType::Dir || depth == 0 || ancestors.contains(&(entry.dev, entry.ino)) {
+            return None;
+        }
+        match read_dir(&entry.path, &self.opts, 0) {
+            Ok(mut childs) => {
+                sort_entries(&mut childs);
+                Some(childs)
+            }
+            Err(err) => {
+                report(err);
+                None
+            }
+        }
+    }
+
+    fn write_level(
+        &self,
+        out: &mut impl Write,
+        entries: Vec<Entry>,
+        depth: u64,
+        level: usize,
+        ancestors: &mut Vec<(u64, u64)>,
+    ) -> Result<()> {
+        if self.format == OutputFormat::Json {
+            write!(out, "[")?;
+        }
+
+        for (i, entry) in entries.into_iter().enumerate() {
+            let childs = self.children(&entry, depth, ancestors);
+            ancestors.push((entry.dev, entry.ino));
+            match self.format {
+                OutputFormat::Json => {
+                    if i > 0 {
+                        write!(out, ",")?;
+                    }
+                    write!(out, "{},\"children\":", self.json_object(&entry, level)?)?;
+                    match childs {
+                        Some(childs) => {
+                            self.write_level(out, childs, depth - 1, level + 1, ancestors)?
+                        }
+                        None => write!(out, "[]")?,
+                    }
+                    write!(out, "}}")?;
+                }
+                OutputFormat::Ndjson => {
+                    writeln!(out, "{}}}", self.json_object(&entry, level)?)?;
+                    if let Some(childs) = childs {
+                        self.write_level(out, childs, depth - 1, level + 1, ancestors)?;
+                    }
+                }
+                OutputFormat::Csv => {
+                    writeln!(out, "{}", self.csv_row(&entry, level)?)?;
+                    if let Some(childs) = childs {
+                        self.write_level(out, childs, depth - 1, level + 1, ancestors)?;
+                    }
+                }
+            }
+            ancestors.pop();
+        }
+
+        if self.format == OutputFormat::Json {
+            write!(out, "]")?;
+        }
+        Ok(())
+    }
+
+    /// with a single path its contents are written, with more than one each
+    /// path is written as a root entry holding its contents
+    pub fn write(&self, paths: &[PathBuf], depth: u64) -> Result<()> {
+        let mut out = BufWriter::new(io::stdout().lock());
+        if self.format == OutputFormat::Csv {
+            let rendered = if self.rendered { ",rendered" } else { "" };
+            writeln!(out, "{CSV_HEADER}{rendered}")?;
+        }
+
+        if let [path] = paths {
+            let entries = match read_dir(path, &self.opts, 0) {
+                Ok(mut entries) => {
+                    sort_entries(&mut entries);
+                    entries
+                }
+                Err(err) => {
+                    report(err);
+                    Vec::new()
+                }
+            };
+            let mut ancestors: Vec<_> = path
+                .metadata()
+                .map(|m| (m.dev(), m.ino()))
+                .into_iter()
+                .collect();
+            self.write_level(&mut out, entries, depth, 0, &mut ancestors)?;
+        } else {
+            let mut roots = Vec::new();
+            for path in paths {
+                match process_path(path.clone(), &self.opts, 0) {
+                    Ok(root) => roots.push(root),
+                    Err(err) => report(err),
+                }
+            }
+            self.write_level(&mut out, roots, depth + 1, 0, &mut Vec::new())?;
+        }
+
+        if self.format == OutputFormat::Json {
+            writeln!(out)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    fs::File,
+    io::Read,
+    iter::repeat_n,
+    path::{Path, PathBuf},
+    sync::{
+        LazyLock, Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+};
+
+use mlua::{Either, Function, Lua};
+use unicode_width::UnicodeWidthChar;
+
+use crate::{
+    api::get_previews,
+    error::take_errors,
+    fmt::{_format_file, by_mime},
+    init_globals,
+    meta::EntryMeta,
+    run_config,
+    sort::sort_entries,
+    util::*,
+};
+
+/// previewers registered with `load_previews`, keyed like the formats
+pub static PREVIEWS: LazyLock<Mutex<HashMap<FileType, Function>>> = LazyLock::new(Default::default);
+
+/// files are never read further than this
+const READ_CAP: u64 = 64 * 1024;
+const TAB_WIDTH: usize = 8;
+
+/// what the explorer asks for, the size is the one of the pane
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub path: PathBuf,
+    pub width: usize,
+    pub height: usize,
+}
+
+pub struct Preview {
+    pub req: Request,
+    pub lines: Vec<Format>,
+}
+
+/// the previewers get a lua state of their own, so one running a command never
+/// keeps the listing from being formatted. The config runs again in it, only
+/// `load_previews` does something there
+fn preview_lua() -> anyhow::Result<Lua> {
+    let lua = Lua::new();
+    init_globals(&lua)?;
+    lua.globals()
+        .set("load_previews", lua.create_function(get_previews)?)?;
+    let ignored = lua.create_function(|_, _: mlua::Value| Ok(()))?;
+    lua.globals().set("load_formats", ignored.clone())?;
+    lua.globals().set("load_layout", ignored)?;
+    run_config(&lua)?;
+    Ok(lua)
+}
+
+/// starts the thread previews are made on. Requests that pile up while one is
+/// being made are skipped for the newest
+pub fn spawn() -> (Sender<Request>, Receiver<Preview>) {
+    let (req_tx, req_rx) = mpsc::channel::<Request>();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        // the config already ran once without errors, if it fails here the
+        // builtin previews are still there
+        let _lua = preview_lua();
+        while let Ok(mut req) = req_rx.recv() {
+            while let Ok(newer) = req_rx.try_recv() {
+                req = newer;
+            }
+            let lines = preview(&req);
+            if tx.send(Preview { req, lines }).is_err() {
+                break;
+            }
+        }
+    });
+    (req_tx, rx)
+}
+
+/// the previewer registered for the type of the entry, if any
+pub fn previewer(entry: &Entry) -> Option<Function> {
+    let map = PREVIEWS.lock().unwrap();
+    // files are only read for their type when a previewer is keyed on it,
+    // what the listing does is decided by the formats alone
+    let mime = || {
+        map.keys()
+            .any(|ft| matches!(ft, FileType::Mime(_)))
+            .then(|| by_mime(&map, entry))
+            .flatten()
+    };
+    let previewer = match entry.ty {
+        EntryType::File => _format_file(&map, &entry.path)
+            .or_else(mime)
+            .or_else(|| map.get(&FileType::GenericFile)),
+        EntryType::Executable => _format_file(&map, &entry.path)
+            .or_else(mime)
+            .or_else(|| map.get(&FileType::GenericExecutable)),
+        EntryType::Dir => entry
+            .path
+            .file_name()
+            .and_then(|n| map.get(&FileType::OtherDir(n.to_string_lossy().into_owned())))
+            .or_else(|| map.get(&FileType::GenericDir)),
+        EntryType::SymLink => map.get(&FileType::GenericSymLink),
+        EntryType::Fifo => map.get(&FileType::GenericFifo),
+        EntryType::Socket => map.get(&FileType::GenericSocket),
+        EntryType::BlockDevice => map.get(&FileType::GenericBlockDevice),
+        EntryType::CharDevice => map.get(&FileType::GenericCharDevice),
+    };
+    previewer.cloned()
+}
+
+fn styled(text: &str, style: Style) -> Format {
+    let mut fmt = Format::from("");
+    fmt.push(text, style);
+    fmt
+}
+
+pub fn error(text: &str) -> Vec<Format> {
+    vec![styled(text, Style::from(Color::RED))]
+}
+
+fn dim(text: &str) -> Vec<Format> {
+    let style = Style {
+        attrs: DIM,
+        ..Default::default()
+    };
+    vec![styled(text, style)]
+}
+
+/// links are previewed as what they point to, the lua previewer goes first and
+/// the builtin one is used when it returns nil
+pub fn preview(req: &Request) -> Vec<Format> {
+    let opts = ReadOptions {
+        hidden: true,
+        follow: true,
+    };
+    let entry = match process_path(req.path.clone(), &opts, 0) {
+        Ok(entry) => entry,
+        Err(err) => return error(&err.to_string()),
+    };
+
+    if let Some(previewer) = previewer(&entry) {
+        let meta = EntryMeta::new(&entry, 0);
+        let args = (entry.path.as_path(), meta, req.width, req.height);
+        match previewer.call::<Option<Either<String, Vec<Format>>>>(args) {
+            Ok(None) => {}
+            Ok(Some(Either::Left(text))) => return text_lines(&text, req.height),
+            Ok(Some(Either::Right(lines))) => return lines,
+            Err(err) => return error(&err.to_string()),
+        }
+    }
+
+    match entry.ty {
+        EntryType::Dir => dir_lines(&entry.path, req.height),
+        EntryType::File | EntryType::Executable => file_lines(&entry.path, req.height),
+        _ => meta_lines(&entry),
+    }
+}
+
+/// the children formatted like in the listing
+fn dir_lines(path: &PathBuf, height: usize) -> Vec<Format> {
+    let mut entries = match read_dir(path, &ReadOptions::default(), 0) {
+        Ok(entries) => entries,
+        Err(err) => return error(&err.to_string()),
+    };
+    // what couldn't be read is simply not previewed
+    take_errors();
+    if entries.is_empty() {
+        return dim("empty directory");
+    }
+    sort_entries(&mut entries);
+    entries
+        .iter()
+        .take(height)
+        .map(|e| {
+            Format::from_entry(e, 0).unwrap_or_else(|_| Format::from(e.display_name().as_str()))
+        })
+        .collect()
+}
+
+/// text, or a hex dump when it doesn't look like some
+fn file_lines(path: &Path, height: usize) -> Vec<Format> {
+    let mut buf = Vec::new();
+    if let Err(err) = File::open(path).and_then(|f| f.take(READ_CAP).read_to_end(&mut buf)) {
+        return error(&err.to_string());
+    }
+    if buf.is_empty() {
+        return dim("empty file");
+    }
+    match as_text(&buf) {
+        Some(text) => text_lines(text, height),
+        None => hex_lines(&buf, height),
+    }
+}
+
+/// the bytes as utf-8 if they hold no NUL, a character cut by the read cap is
+/// left out
+fn as_text(buf: &[u8]) -> Option<&str> {
+    if buf.contains(&0) {
+        return None;
+    }
+    match std::str::from_utf8(buf) {
+        Ok(text) => Some(text),
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&buf[..err.valid_up_to()]).ok()
+        }
+        Err(_) => None,
+    }
+}
+
+/// tabs are expanded and control characters escaped, eg: `^[`, the escapes are
+/// dimmed so they can't be mistaken for the text
+pub fn text_lines(text: &str, height: usize) -> Vec<Format> {
+    let escape = Style {
+        attrs: DIM,
+        ..Default::default()
+    };
+    text.lines()
+        .take(height)
+        .map(|line| {
+            let mut fmt = Format::from("");
+            let mut col = 0;
+            for c in line.chars() {
+                match c {
+                    '\t' => {
+                        let n = TAB_WIDTH - col % TAB_WIDTH;
+                        fmt.push(&repeat_n(' ', n).collect::<String>(), Style::default());
+                        col += n;
+                    }
+                    c if c.is_control() => {
+                        let esc = match c as u32 {
+                            0x7f => "^?".to_string(),
+                            n if n < 0x20 => format!("^{}", char::from(n as u8 + 0x40)),
+                            _ => c.escape_unicode().to_string(),
+                        };
+                        col += esc.len();
+                        fmt.push(&esc, escape);
+                    }
+                    c => {
+                        col += c.width().unwrap_or(0);
+                        fmt.push(c.encode_utf8(&mut [0; 4]), Style::default());
+                    }
+                }
+            }
+            fmt
+        })
+        .collect()
+}
+
+/// `hexdump -C` like, 16 bytes a line
+fn hex_lines(buf: &[u8], height: usize) -> Vec<Format> {
+    buf.chunks(16)
+        .take(height)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut hex = String::new();
+            for j in 0..16 {
+                if j == 8 {
+                    hex.push(' ');
+                }
+                match chunk.get(j) {
+                    Some(b) => write!(hex, "{b:02x} ").unwrap(),
+                    None => hex.push_str("   "),
+                }
+            }
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| {
+                    if b == b' ' || b.is_ascii_graphic() {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            Format::from(format!("{:08x}  {hex} |{ascii}|", i * 16).as_str())
+        })
+        .collect()
+}
+
+/// what can be said about the entries that can't be read
+fn meta_lines(entry: &Entry) -> Vec<Format> {
+    let mut rows = vec![
+        ("type", entry.ty.name().to_string()),
+        ("mode", entry.mode_string()),
+        ("owner", format!("{}:{}", entry.user, entry.group)),
+    ];
+    match entry.ty {
+        EntryType::BlockDevice | EntryType::CharDevice => rows.push((
+            "device",
+            format!("{}, {}", libc::major(entry.rdev), libc::minor(entry.rdev)),
+        )),
+        _ => rows.push(("size", entry.size.to_string())),
+    }
+    rows.push(("modified", format_time(entry.mtime)));
+    if let Some(target) = &entry.target {
+        rows.push(("target", target.to_string_lossy().into_owned()));
+    }
+    if entry.broken {
+        rows.push(("", "the target doesn't exist".to_string()));
+    }
+
+    let label = Style {
+        attrs: BOLD,
+        ..Default::default()
+    };
+    rows.into_iter()
+        .map(|(key, value)| {
+            let mut fmt = Format::from("");
+            fmt.push(&format!("{key:<10}"), label);
+            fmt.push(&value, Style::default());
+            fmt
+        })
+        .collect()
+}
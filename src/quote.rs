@@ -0,0 +1,88 @@
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+use clap::ValueEnum;
+
+/// how file names are written, same names as `ls --quoting-style`
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum QuotingStyle {
+    /// as is, unprintable characters become `?`
+    #[default]
+    Literal,
+    /// single quoted when the shell would need it, unprintable characters become `?`
+    Shell,
+    /// backslash escapes without quotes
+    Escape,
+    /// double quoted with C escapes
+    C,
+}
+
+/// characters that make a name need quoting in a shell
+const SHELL_SPECIAL: &str = " \t\n!\"#$&'()*;<=>?[\\]^`{|}~";
+
+fn c_escape(c: char, out: &mut String) {
+    match c {
+        '\n' => out.push_str("\\n"),
+        '\t' => out.push_str("\\t"),
+        '\r' => out.push_str("\\r"),
+        '\x07' => out.push_str("\\a"),
+        '\x08' => out.push_str("\\b"),
+        '\x0b' => out.push_str("\\v"),
+        '\x0c' => out.push_str("\\f"),
+        '\x1b' => out.push_str("\\e"),
+        '\\' => out.push_str("\\\\"),
+        c if c.is_control() => {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                out.push_str(&format!("\\{b:03o}"));
+            }
+        }
+        c => out.push(c),
+    }
+}
+
+/// renders a file name safely for the terminal in the given style, bytes that
+/// are not valid utf-8 are never dropped silently
+pub fn quote(name: &OsStr, style: QuotingStyle) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut needs_quotes = false;
+
+    for chunk in name.as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match style {
+                QuotingStyle::Literal => out.push(if c.is_control() { '?' } else { c }),
+                QuotingStyle::Shell => {
+                    needs_quotes |= SHELL_SPECIAL.contains(c) || c.is_control();
+                    match c {
+                        '\'' => out.push_str("'\\''"),
+                        c if c.is_control() => out.push('?'),
+                        c => out.push(c),
+                    }
+                }
+                QuotingStyle::Escape => match c {
+                    ' ' => out.push_str("\\ "),
+                    c => c_escape(c, &mut out),
+                },
+                QuotingStyle::C => match c {
+                    '"' => out.push_str("\\\""),
+                    c => c_escape(c, &mut out),
+                },
+            }
+        }
+
+        for b in chunk.invalid() {
+            match style {
+                QuotingStyle::Literal | QuotingStyle::Shell => {
+                    needs_quotes = true;
+                    out.push('?');
+                }
+                QuotingStyle::Escape | QuotingStyle::C => out.push_str(&format!("\\{b:03o}")),
+            }
+        }
+    }
+
+    match style {
+        QuotingStyle::Shell if needs_quotes || out.is_empty() => format!("'{out}'"),
+        QuotingStyle::C => format!("\"{out}\""),
+        _ => out,
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::Mutex;
+
+use mlua::{Function, Table};
+use regex::{Regex, RegexBuilder};
+
+use crate::{glob::glob_match, util::Entry};
+
+/// what a rule looks at, in order of precedence
+pub enum Matcher {
+    /// the full path, wildcards allowed
+    Path(String),
+    /// the exact file name
+    Name(String),
+    /// a wildcard pattern on the file name
+    Glob(String),
+    /// a regular expression on the file name
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn rank(&self) -> u8 {
+        match self {
+            Matcher::Path(_) => 0,
+            Matcher::Name(_) => 1,
+            Matcher::Glob(_) => 2,
+            Matcher::Regex(_) => 3,
+        }
+    }
+}
+
+pub struct Rule {
+    matcher: Matcher,
+    ignore_case: bool,
+    /// only entries of this type, eg: `dir`
+    ty: Option<String>,
+    pub format: Function,
+}
+
+/// sorted by precedence, rules of the same kind keep the order they were given in
+pub static RULES: Mutex<Vec<Rule>> = Mutex::new(Vec::new());
+
+fn fold(s: &str, ignore_case: bool) -> String {
+    if ignore_case {
+        s.to_lowercase()
+    } else {
+        s.to_string()
+    }
+}
+
+impl Rule {
+    /// `{name=..., format=fn}`, `glob`, `regex` or `path` in place of `name`.
+    /// `ignore_case` and `type` are optional
+    pub fn from_table(t: &Table) -> mlua::Result<Rule> {
+        let ignore_case = t.get::<Option<bool>>("ignore_case")?.unwrap_or(false);
+        let matcher = if let Some(path) = t.get::<Option<String>>("path")? {
+            Matcher::Path(fold(&path, ignore_case))
+        } else if let Some(name) = t.get::<Option<String>>("name")? {
+            Matcher::Name(fold(&name, ignore_case))
+        } else if let Some(glob) = t.get::<Option<String>>("glob")? {
+            Matcher::Glob(fold(&glob, ignore_case))
+        } else if let Some(regex) = t.get::<Option<String>>("regex")? {
+            let regex = RegexBuilder::new(&regex)
+                .case_insensitive(ignore_case)
+                .build()
+                .map_err(|err| mlua::Error::RuntimeError(format!("invalid rule regex: {err}")))?;
+            Matcher::Regex(regex)
+        } else {
+            return Err(mlua::Error::RuntimeError(
+                "a rule needs one of `path`, `name`, `glob` or `regex`".to_string(),
+            ));
+        };
+
+        Ok(Rule {
+            matcher,
+            ignore_case,
+            ty: t.get("type")?,
+            format: t.get("format")?,
+        })
+    }
+
+    fn matches(&self, entry: &Entry) -> bool {
+        if self.ty.as_deref().is_some_and(|ty| ty != entry.ty.name()) {
+            return false;
+        }
+        match &self.matcher {
+            Matcher::Path(pattern) => {
+                let path = fold(&entry.path.to_string_lossy(), self.ignore_case);
+                glob_match(pattern, &path)
+            }
+            Matcher::Name(name) => *name == fold(&entry.name, self.ignore_case),
+            Matcher::Glob(pattern) => glob_match(pattern, &fold(&entry.name, self.ignore_case)),
+            Matcher::Regex(regex) => regex.is_match(&entry.name),
+        }
+    }
+}
+
+pub fn add_rules(rules: Vec<Rule>) {
+    if let Ok(mut all) = RULES.lock() {
+        all.extend(rules);
+        all.sort_by_key(|rule| rule.matcher.rank());
+    }
+}
+
+/// the formatter of the first rule matching the entry
+pub fn match_rule(entry: &Entry) -> Option<Function> {
+    let rules = RULES.lock().ok()?;
+    let rule = rules.iter().find(|rule| rule.matches(entry))?;
+    Some(rule.format.clone())
+}
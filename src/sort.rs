@@ -0,0 +1,131 @@
+use std::{cmp::Ordering, iter::Peekable, str::Chars};
+
+use crate::{
+    get_options,
+    util::{Entry, EntryType, SortBy},
+};
+
+fn cmp_name(a: &Entry, b: &Entry) -> Ordering {
+    a.name.to_lowercase().cmp(&b.name.to_lowercase())
+}
+
+fn sort_name(a: &Entry, b: &Entry) -> Ordering {
+    let v = cmp_name(a, b);
+    if let Ordering::Equal = v {
+        return a.ty.cmp(&b.ty);
+    }
+    v
+}
+
+fn sort_type(a: &Entry, b: &Entry) -> Ordering {
+    let v = a.ty.cmp(&b.ty);
+    if let Ordering::Equal = v {
+        return cmp_name(a, b);
+    }
+    v
+}
+
+/// biggest first, like `ls -S`
+fn sort_size(a: &Entry, b: &Entry) -> Ordering {
+    b.size.cmp(&a.size).then_with(|| sort_name(a, b))
+}
+
+fn extension(e: &Entry) -> String {
+    match e.name.rsplit_once('.') {
+        // dotfiles without another dot have no extension
+        Some((stem, ext)) if !stem.is_empty() => ext.to_lowercase(),
+        _ => String::new(),
+    }
+}
+
+fn sort_extension(a: &Entry, b: &Entry) -> Ordering {
+    extension(a)
+        .cmp(&extension(b))
+        .then_with(|| sort_name(a, b))
+}
+
+fn take_number(it: &mut Peekable<Chars>) -> String {
+    let mut n = String::new();
+    while let Some(c) = it.next_if(|c| c.is_ascii_digit()) {
+        n.push(c);
+    }
+    n
+}
+
+/// compares runs of digits by their numeric value so `v1.9` sorts before `v1.10`
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        let (ca, cb) = match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ca), Some(cb)) => (*ca, *cb),
+        };
+
+        if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let na = take_number(&mut a);
+            let nb = take_number(&mut b);
+            let ta = na.trim_start_matches('0');
+            let tb = nb.trim_start_matches('0');
+            let v = ta
+                .len()
+                .cmp(&tb.len())
+                .then_with(|| ta.cmp(tb))
+                // `01` after `1`
+                .then_with(|| na.len().cmp(&nb.len()));
+            if v != Ordering::Equal {
+                return v;
+            }
+            continue;
+        }
+
+        let v = ca.to_lowercase().cmp(cb.to_lowercase());
+        if v != Ordering::Equal {
+            return v;
+        }
+        a.next();
+        b.next();
+    }
+}
+
+fn sort_version(a: &Entry, b: &Entry) -> Ordering {
+    natural_cmp(&a.name, &b.name).then_with(|| sort_name(a, b))
+}
+
+/// compares two entries using the global sorting options
+pub fn compare(a: &Entry, b: &Entry) -> Ordering {
+    let opts = get_options();
+
+    if opts.dirs_first {
+        let a_dir = a.ty == EntryType::Dir;
+        let b_dir = b.ty == EntryType::Dir;
+        if a_dir != b_dir {
+            return b_dir.cmp(&a_dir);
+        }
+    }
+
+    let v = match opts.sort_by {
+        SortBy::Name => sort_name(a, b),
+        SortBy::Type => sort_type(a, b),
+        SortBy::Size => sort_size(a, b),
+        // newest first, like `ls -t`
+        SortBy::Modified => b.mtime.cmp(&a.mtime).then_with(|| sort_name(a, b)),
+        SortBy::Changed => b.ctime.cmp(&a.ctime).then_with(|| sort_name(a, b)),
+        SortBy::Accessed => b.atime.cmp(&a.atime).then_with(|| sort_name(a, b)),
+        SortBy::Extension => sort_extension(a, b),
+        SortBy::Version => sort_version(a, b),
+    };
+
+    if opts.reverse { v.reverse() } else { v }
+}
+
+/// sorts the entries and, level by level, all of their childs
+pub fn sort_entries(entries: &mut [Entry]) {
+    entries.sort_by(compare);
+    for entry in entries {
+        sort_entries(&mut entry.childs);
+    }
+}
@@ -0,0 +1,35 @@
+use std::{collections::HashMap, fs::read_to_string, sync::LazyLock};
+
+static USERS: LazyLock<HashMap<u32, String>> = LazyLock::new(|| load_names("/etc/passwd"));
+static GROUPS: LazyLock<HashMap<u32, String>> = LazyLock::new(|| load_names("/etc/group"));
+
+/// both /etc/passwd and /etc/group are `name:password:id:...`
+fn load_names(path: &str) -> HashMap<u32, String> {
+    let mut map = HashMap::new();
+    let Ok(buf) = read_to_string(path) else {
+        return map;
+    };
+
+    for line in buf.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split(':');
+        let (Some(name), Some(_), Some(id)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        if let Ok(id) = id.parse() {
+            map.entry(id).or_insert_with(|| name.to_string());
+        }
+    }
+
+    map
+}
+
+pub fn user_name(uid: u32) -> String {
+    USERS.get(&uid).cloned().unwrap_or_else(|| uid.to_string())
+}
+
+pub fn group_name(gid: u32) -> String {
+    GROUPS.get(&gid).cloned().unwrap_or_else(|| gid.to_string())
+}
@@ -1,20 +1,37 @@
 use std::{
     env::current_dir,
+    ffi::{OsStr, OsString},
     fmt::Display,
-    fs::DirEntry,
-    os::unix::fs::MetadataExt,
+    fs::Metadata,
+    io::IsTerminal,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, MetadataExt},
+    },
     path::PathBuf,
+    sync::{Mutex, OnceLock},
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{Result, anyhow};
+use anyhow::Result;
 use clap::ValueEnum;
-use mlua::{Either, FromLua, ObjectLike, Table};
-
-use crate::fmt::{format_dir, format_file, format_link};
+use mlua::{Either, FromLua, Function, IntoLua, ObjectLike, Table};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    error::{EntryError, report},
+    fmt::{format_dir, format_exec, format_file, format_generic, format_link},
+    get_options,
+    meta::EntryMeta,
+    quote::quote,
+    rules::match_rule,
+    users::{group_name, user_name},
+};
 
 #[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
 #[repr(u8)]
+#[allow(non_camel_case_types)]
 pub enum Color {
     BLACK = 30,
     RED = 31,
@@ -26,9 +43,69 @@ pub enum Color {
     #[default]
     WHITE = 37,
 
+    BRIGHT_BLACK = 90,
+    BRIGHT_RED = 91,
+    BRIGHT_GREEN = 92,
+    BRIGHT_YELLOW = 93,
+    BRIGHT_BLUE = 94,
+    BRIGHT_MAGENTA = 95,
+    BRIGHT_CYAN = 96,
+    BRIGHT_WHITE = 97,
+
+    /// index in the 256 color palette
+    PALETTE(u8),
     RGB(u8, u8, u8),
 }
 
+const COLOR_NAMES: [(&str, Color); 16] = [
+    ("black", Color::BLACK),
+    ("red", Color::RED),
+    ("green", Color::GREEN),
+    ("yellow", Color::YELLOW),
+    ("blue", Color::BLUE),
+    ("magenta", Color::MAGENTA),
+    ("cyan", Color::CYAN),
+    ("white", Color::WHITE),
+    ("bright_black", Color::BRIGHT_BLACK),
+    ("bright_red", Color::BRIGHT_RED),
+    ("bright_green", Color::BRIGHT_GREEN),
+    ("bright_yellow", Color::BRIGHT_YELLOW),
+    ("bright_blue", Color::BRIGHT_BLUE),
+    ("bright_magenta", Color::BRIGHT_MAGENTA),
+    ("bright_cyan", Color::BRIGHT_CYAN),
+    ("bright_white", Color::BRIGHT_WHITE),
+];
+
+impl Color {
+    /// the SGR parameters that set this color, as foreground or background
+    pub fn sgr(&self, background: bool) -> String {
+        let base = if background { 40 } else { 30 };
+        match self {
+            Self::PALETTE(n) => format!("{};5;{n}", base + 8),
+            Self::RGB(r, g, b) => format!("{};2;{r};{g};{b}", base + 8),
+            named => {
+                let i = COLOR_NAMES.iter().position(|(_, c)| c == named).unwrap() as u8;
+                let code = if i < 8 { 30 + i } else { 90 + i - 8 };
+                (code + base - 30).to_string()
+            }
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Color> {
+        let name = name.to_lowercase();
+        if let Some(hex) = name.strip_prefix('#') {
+            let v = u32::from_str_radix(hex, 16)
+                .ok()
+                .filter(|_| hex.len() == 6)?;
+            return Some(Color::RGB((v >> 16) as u8, (v >> 8) as u8, v as u8));
+        }
+        COLOR_NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, col)| *col)
+    }
+}
+
 impl FromLua for Color {
     fn from_lua(value: mlua::Value, _: &mlua::Lua) -> mlua::Result<Self> {
         match value {
@@ -39,24 +116,17 @@ impl FromLua for Color {
 
                 return Ok(Color::RGB(r, g, b));
             }
+            mlua::Value::Integer(n) if (0..=255).contains(&n) => {
+                return Ok(Color::PALETTE(n as u8));
+            }
             mlua::Value::String(s) => {
-                return Ok(match s.to_string_lossy().as_str() {
-                    "BLACK" | "black" => Color::BLACK,
-                    "RED" | "red" => Color::RED,
-                    "GREEN" | "green" => Color::GREEN,
-                    "YELLOW" | "yellow" => Color::YELLOW,
-                    "BLUE" | "blue" => Color::BLUE,
-                    "MAGENTA" | "magenta" => Color::MAGENTA,
-                    "CYAN" | "cyan" => Color::CYAN,
-                    "WHITE" | "white" => Color::WHITE,
-                    _ => {
-                        return Err(mlua::Error::FromLuaConversionError {
-                            from: "mlua::Value",
-                            to: "Color".to_string(),
-                            message: Some("not a valid name".to_string()),
-                        });
-                    }
-                });
+                return Color::from_name(&s.to_string_lossy()).ok_or(
+                    mlua::Error::FromLuaConversionError {
+                        from: "mlua::Value",
+                        to: "Color".to_string(),
+                        message: Some("not a valid name".to_string()),
+                    },
+                );
             }
             _ => {
                 let v = value.to_string()?;
@@ -70,19 +140,196 @@ impl FromLua for Color {
     }
 }
 
+impl IntoLua for Color {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        match self {
+            Color::PALETTE(n) => n.into_lua(lua),
+            Color::RGB(r, g, b) => lua.create_sequence_from([r, g, b])?.into_lua(lua),
+            named => {
+                let (name, _) = COLOR_NAMES.iter().find(|(_, c)| *c == named).unwrap();
+                name.into_lua(lua)
+            }
+        }
+    }
+}
+
+pub type Attributes = u8;
+pub const BOLD: Attributes = 0b0000001;
+pub const DIM: Attributes = 0b0000010;
+pub const ITALIC: Attributes = 0b0000100;
+pub const UNDERLINE: Attributes = 0b0001000;
+pub const BLINK: Attributes = 0b0010000;
+pub const REVERSE: Attributes = 0b0100000;
+pub const STRIKETHROUGH: Attributes = 0b1000000;
+
+/// lua key, attribute and SGR code
+pub const ATTRIBUTES: [(&str, Attributes, u8); 7] = [
+    ("bold", BOLD, 1),
+    ("dim", DIM, 2),
+    ("italic", ITALIC, 3),
+    ("underline", UNDERLINE, 4),
+    ("blink", BLINK, 5),
+    ("reverse", REVERSE, 7),
+    ("strikethrough", STRIKETHROUGH, 9),
+];
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Option<Color>,
+    pub attrs: Attributes,
+}
+
+impl From<Color> for Style {
+    fn from(fg: Color) -> Self {
+        Style {
+            fg,
+            ..Default::default()
+        }
+    }
+}
+
+impl Style {
+    /// reads the `fg`, `bg` and attribute keys of a table over the current style
+    pub fn merge_table(&mut self, t: &Table) -> mlua::Result<()> {
+        if let Some(fg) = t.get::<Option<Color>>("fg")? {
+            self.fg = fg;
+        }
+        if let Some(bg) = t.get::<Option<Color>>("bg")? {
+            self.bg = Some(bg);
+        }
+        for (key, attr, _) in ATTRIBUTES {
+            match t.get::<Option<bool>>(key)? {
+                Some(true) => self.attrs |= attr,
+                Some(false) => self.attrs &= !attr,
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// the full escape sequence, starting from a reset
+    fn escape(&self) -> String {
+        let mut codes = vec!["0".to_string()];
+        for (_, attr, code) in ATTRIBUTES {
+            if self.attrs & attr != 0 {
+                codes.push(code.to_string());
+            }
+        }
+        codes.push(self.fg.sgr(false));
+        if let Some(bg) = self.bg {
+            codes.push(bg.sgr(true));
+        }
+        format!("\x1b[{}m", codes.join(";"))
+    }
+}
+
+/// either a plain color or a style table like `{fg=..., bg=..., bold=true}`
+impl FromLua for Style {
+    fn from_lua(value: mlua::Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        match value {
+            mlua::Value::Table(t) if t.raw_len() == 0 => {
+                let mut style = Style::default();
+                style.merge_table(&t)?;
+                Ok(style)
+            }
+            value => Ok(Style::from(Color::from_lua(value, lua)?)),
+        }
+    }
+}
+
+impl IntoLua for Style {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        let t = lua.create_table()?;
+        t.set("fg", self.fg)?;
+        t.set("bg", self.bg)?;
+        for (key, attr, _) in ATTRIBUTES {
+            if self.attrs & attr != 0 {
+                t.set(key, true)?;
+            }
+        }
+        t.into_lua(lua)
+    }
+}
+
+/// color of the symlinks whose target does not exist, `or` in LS_COLORS
+pub static BROKEN_LINK_COLOR: Mutex<Color> = Mutex::new(Color::RED);
+
+#[derive(ValueEnum, Debug, Default, Clone, Copy)]
+pub enum ColorMode {
+    /// only when writing to a terminal, honoring NO_COLOR and CLICOLOR_FORCE
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// whether escape codes are written at all, decided once per run
+pub fn use_color() -> bool {
+    static USE_COLOR: OnceLock<bool> = OnceLock::new();
+    *USE_COLOR.get_or_init(|| {
+        let set = |var| std::env::var_os(var).is_some_and(|v| !v.is_empty() && v != "0");
+        match get_options().color {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto if set("CLICOLOR_FORCE") => true,
+            ColorMode::Auto if std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) => false,
+            ColorMode::Auto => std::io::stdout().is_terminal(),
+        }
+    })
+}
+
+/// clears every attribute, empty without colors
+pub fn reset() -> &'static str {
+    if use_color() { "\x1b[0m" } else { "" }
+}
+
 #[derive(ValueEnum, Debug, Default, Clone)]
 pub enum SortBy {
     #[default]
     Name,
     Type,
+    /// biggest first
+    Size,
+    /// newest modification time first
+    Modified,
+    /// newest status change time first
+    Changed,
+    /// newest access time first
+    Accessed,
+    Extension,
+    /// natural order, `v1.9` before `v1.10`
+    Version,
 }
 
-#[derive(Debug, Default, PartialEq, PartialOrd, Eq, Ord)]
+#[derive(Debug, Default, PartialEq, PartialOrd, Eq, Ord, Clone, Copy)]
 pub enum EntryType {
     #[default]
     File,
     Dir,
     SymLink,
+    /// regular file with any of the execute bits set
+    Executable,
+    Fifo,
+    Socket,
+    BlockDevice,
+    CharDevice,
+}
+
+impl EntryType {
+    /// the name used in the machine readable output and by lua
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryType::File => "file",
+            EntryType::Dir => "dir",
+            EntryType::SymLink => "symlink",
+            EntryType::Executable => "executable",
+            EntryType::Fifo => "fifo",
+            EntryType::Socket => "socket",
+            EntryType::BlockDevice => "block",
+            EntryType::CharDevice => "char",
+        }
+    }
 }
 
 #[derive(Debug, Default, Eq, PartialEq, PartialOrd, Hash, Clone)]
@@ -91,157 +338,543 @@ pub enum FileType {
     GenericFile,
     GenericDir,
     GenericSymLink,
+    GenericExecutable,
+    GenericFifo,
+    GenericSocket,
+    GenericBlockDevice,
+    GenericCharDevice,
     OtherDir(String),
     OtherFile(String),
+    /// content based, eg: `image/png` or `image/*`
+    Mime(String),
 }
 
-#[allow(dead_code)]
 pub type Permissions = u32;
-#[allow(dead_code)]
 const READ: Permissions = 0b001;
-#[allow(dead_code)]
 const WRITE: Permissions = 0b010;
-#[allow(dead_code)]
 const EXEC: Permissions = 0b100;
 
+// special bits, stored in `Entry::special_p`
+const SETUID: Permissions = 0b001;
+const SETGID: Permissions = 0b010;
+const STICKY: Permissions = 0b100;
+
+/// converts an unix `rwx` triplet into `Permissions`
+fn permissions(bits: u32) -> Permissions {
+    let mut p = 0;
+    if bits & 0o4 != 0 {
+        p |= READ;
+    }
+    if bits & 0o2 != 0 {
+        p |= WRITE;
+    }
+    if bits & 0o1 != 0 {
+        p |= EXEC;
+    }
+    p
+}
+
+/// inverse of `permissions`
+fn unix_bits(p: Permissions) -> u32 {
+    let mut bits = 0;
+    if p & READ != 0 {
+        bits |= 0o4;
+    }
+    if p & WRITE != 0 {
+        bits |= 0o2;
+    }
+    if p & EXEC != 0 {
+        bits |= 0o1;
+    }
+    bits
+}
+
 #[derive(Debug)]
 pub struct Entry {
+    /// lossy utf-8 version of `raw_name`, used for sorting and matching
     pub name: String,
+    pub raw_name: OsString,
     pub path: PathBuf,
     pub ty: EntryType,
-
-    // pub user_p  : Permissions,
-    // pub group_p : Permissions,
-    // pub global_p: Permissions,
-
-    // pub user: String,
-    // pub group: String,
-    #[allow(dead_code)]
+    /// where the entry points to if it is a symlink
+    pub target: Option<PathBuf>,
+    /// symlink whose target does not exist
+    pub broken: bool,
+
+    pub user_p: Permissions,
+    pub group_p: Permissions,
+    pub global_p: Permissions,
+    pub special_p: Permissions,
+
+    pub dev: u64,
+    pub ino: u64,
+    /// device number, only meaningful for block and char devices
+    pub rdev: u64,
+    pub nlink: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub user: String,
+    pub group: String,
     pub size: u64,
-    #[allow(dead_code)]
-    pub date: SystemTime,
+    pub mtime: SystemTime,
+    pub ctime: SystemTime,
+    pub atime: SystemTime,
 
     pub childs: Vec<Entry>,
 }
 
-pub fn process_path(path: PathBuf, hidden: bool, depth: u64) -> Result<Entry> {
+impl Entry {
+    /// the name quoted with the global quoting style, safe to print
+    pub fn display_name(&self) -> String {
+        quote(&self.raw_name, get_options().quoting_style)
+    }
+
+    /// permission bits as an unix mode, without the file type
+    pub fn mode_bits(&self) -> u32 {
+        (unix_bits(self.special_p) << 9)
+            | (unix_bits(self.user_p) << 6)
+            | (unix_bits(self.group_p) << 3)
+            | unix_bits(self.global_p)
+    }
+
+    /// `ls -l` style mode string, eg: `drwxr-xr-x`
+    pub fn mode_string(&self) -> String {
+        mode_string(&self.ty, self.mode_bits())
+    }
+}
+
+/// `ls -l` style mode string of a type and its permission bits
+pub fn mode_string(ty: &EntryType, bits: u32) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(match ty {
+        EntryType::File | EntryType::Executable => '-',
+        EntryType::Dir => 'd',
+        EntryType::SymLink => 'l',
+        EntryType::Fifo => 'p',
+        EntryType::Socket => 's',
+        EntryType::BlockDevice => 'b',
+        EntryType::CharDevice => 'c',
+    });
+
+    let special = permissions(bits >> 9);
+    let triplets = [
+        (permissions(bits >> 6), special & SETUID != 0, 's'),
+        (permissions(bits >> 3), special & SETGID != 0, 's'),
+        (permissions(bits), special & STICKY != 0, 't'),
+    ];
+    for (p, special, chr) in triplets {
+        s.push(if p & READ != 0 { 'r' } else { '-' });
+        s.push(if p & WRITE != 0 { 'w' } else { '-' });
+        s.push(match (p & EXEC != 0, special) {
+            (true, true) => chr,
+            (false, true) => chr.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+
+    s
+}
+
+/// the time of a stat field, which is negative before the epoch. Times that
+/// don't fit fall back to the epoch
+fn stat_time(secs: i64, nsec: i64) -> SystemTime {
+    let nsec = Duration::new(0, nsec as u32);
+    let time = if secs >= 0 {
+        UNIX_EPOCH.checked_add(Duration::new(secs as u64, 0))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::new(secs.unsigned_abs(), 0))
+    };
+    time.and_then(|t| t.checked_add(nsec)).unwrap_or(UNIX_EPOCH)
+}
+
+/// seconds since the epoch, negative before it
+pub fn unix_time(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+/// formats a timestamp in local time the way `ls -l` does: the time of day for
+/// recent files and the year for anything older than ~6 months or in the future
+pub fn format_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    const HALF_YEAR: u64 = 60 * 60 * 24 * 365 / 2;
+
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as libc::time_t,
+        Err(e) => -(e.duration().as_secs() as libc::time_t),
+    };
+
+    // SAFETY: localtime_r only writes into the provided tm
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&secs, &mut tm);
+        tm
+    };
+    let month = MONTHS[tm.tm_mon.clamp(0, 11) as usize];
+
+    let recent = match SystemTime::now().duration_since(time) {
+        Ok(age) => age.as_secs() < HALF_YEAR,
+        Err(_) => false,
+    };
+    if recent {
+        format!(
+            "{month} {:>2} {:02}:{:02}",
+            tm.tm_mday, tm.tm_hour, tm.tm_min
+        )
+    } else {
+        format!("{month} {:>2}  {}", tm.tm_mday, tm.tm_year + 1900)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReadOptions {
+    /// include dotfiles
+    pub hidden: bool,
+    /// classify and recurse symlinks by what they point to, `-L`
+    pub follow: bool,
+}
+
+/// (device, inode) of the directories above the one being read, used to
+/// avoid looping forever when following symlinks
+type Ancestors = Vec<(u64, u64)>;
+
+pub fn process_path(path: PathBuf, opts: &ReadOptions, depth: u64) -> Result<Entry, EntryError> {
+    _process_path(path, opts, depth, &mut Vec::new())
+}
+
+pub fn entry_type(meta: &Metadata) -> EntryType {
+    let ft = meta.file_type();
+    if ft.is_dir() {
+        EntryType::Dir
+    } else if ft.is_symlink() {
+        EntryType::SymLink
+    } else if ft.is_fifo() {
+        EntryType::Fifo
+    } else if ft.is_socket() {
+        EntryType::Socket
+    } else if ft.is_block_device() {
+        EntryType::BlockDevice
+    } else if ft.is_char_device() {
+        EntryType::CharDevice
+    } else if meta.mode() & 0o111 != 0 {
+        EntryType::Executable
+    } else {
+        EntryType::File
+    }
+}
+
+pub fn is_hidden(name: &OsStr) -> bool {
+    name.as_bytes().first() == Some(&b'.')
+}
+
+fn _process_path(
+    path: PathBuf,
+    opts: &ReadOptions,
+    depth: u64,
+    ancestors: &mut Ancestors,
+) -> Result<Entry, EntryError> {
     let path = if path.is_relative() {
-        let mut cwd = current_dir()?;
+        let mut cwd = current_dir().map_err(|source| EntryError::Access {
+            path: path.clone(),
+            source,
+        })?;
         cwd.extend(&path);
 
         cwd
     } else {
         path
     };
-    let name = path.file_name().unwrap().to_str().unwrap().to_string();
+    // `/` and paths ending in `..` have no file name, show them whole
+    let raw_name = match path.file_name() {
+        Some(name) => name.to_os_string(),
+        None => path.clone().into_os_string(),
+    };
+    let name = raw_name.to_string_lossy().to_string();
 
-    // WARN: PROBABLY NOT A GOOD IDEA TO DO IT LIKE THIS
-    if name.chars().next().ok_or(anyhow!("no first char?"))? == '.' && !hidden {
-        return Err(anyhow!("error to ignore :)"));
-    }
+    let access = |source| EntryError::Access {
+        path: path.clone(),
+        source,
+    };
 
-    let meta = path.metadata()?;
+    // lstat first so links are never followed by accident
+    let lmeta = path.symlink_metadata().map_err(access)?;
+    let is_link = lmeta.file_type().is_symlink();
+    let (target, broken) = if is_link {
+        let target = std::fs::read_link(&path).map_err(|source| EntryError::ReadLink {
+            path: path.clone(),
+            source,
+        })?;
+        (Some(target), path.metadata().is_err())
+    } else {
+        (None, false)
+    };
+    let meta = if is_link && opts.follow && !broken {
+        path.metadata().map_err(access)?
+    } else {
+        lmeta
+    };
 
     let size = meta.size();
-    let date = UNIX_EPOCH + Duration::new(meta.ctime() as u64, 0);
+    let mtime = stat_time(meta.mtime(), meta.mtime_nsec());
+    let ctime = stat_time(meta.ctime(), meta.ctime_nsec());
+    let atime = stat_time(meta.atime(), meta.atime_nsec());
+
+    let mode = meta.mode();
+    let user_p = permissions(mode >> 6);
+    let group_p = permissions(mode >> 3);
+    let global_p = permissions(mode);
+    let special_p = permissions(mode >> 9);
 
     let mut childs = Vec::new();
-    let ty = if path.is_dir() {
-        if depth > 0 {
-            childs = read_dir(&path, hidden, depth - 1)?;
+    let ty = entry_type(&meta);
+    if ty == EntryType::Dir {
+        let id = (meta.dev(), meta.ino());
+        if depth > 0 && !ancestors.contains(&id) {
+            ancestors.push(id);
+            // the directory is still listed when its contents can't be read
+            match _read_dir(&path, opts, depth - 1, ancestors) {
+                Ok(read) => childs = read,
+                Err(err) => report(err),
+            }
+            ancestors.pop();
         }
-        EntryType::Dir
-    } else if path.is_symlink() {
-        EntryType::SymLink
-    } else {
-        EntryType::File
-    };
+    }
 
     Ok(Entry {
         name,
+        raw_name,
         path,
-        size,
-        date,
         ty,
+        target,
+        broken,
+        user_p,
+        group_p,
+        global_p,
+        special_p,
+        dev: meta.dev(),
+        ino: meta.ino(),
+        rdev: meta.rdev(),
+        nlink: meta.nlink(),
+        uid: meta.uid(),
+        gid: meta.gid(),
+        user: user_name(meta.uid()),
+        group: group_name(meta.gid()),
+        size,
+        mtime,
+        ctime,
+        atime,
         childs,
     })
 }
 
-fn process_entry(entry: std::io::Result<DirEntry>, hidden: bool, depth: u64) -> Result<Entry> {
-    let entry = entry?;
-    return process_path(entry.path(), hidden, depth);
-}
-
-pub fn read_dir(path: &PathBuf, hidden: bool, depth: u64) -> Result<Vec<Entry>> {
+fn _read_dir(
+    path: &PathBuf,
+    opts: &ReadOptions,
+    depth: u64,
+    ancestors: &mut Ancestors,
+) -> Result<Vec<Entry>, EntryError> {
     let mut v = Vec::new();
-    let dir = std::fs::read_dir(path)?;
+    let dir = std::fs::read_dir(path).map_err(|source| EntryError::OpenDir {
+        path: path.clone(),
+        source,
+    })?;
 
     for entry in dir {
-        if let Ok(k) = process_entry(entry, hidden, depth) {
-            v.push(k);
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(source) => {
+                report(EntryError::ReadDir {
+                    path: path.clone(),
+                    source,
+                });
+                continue;
+            }
+        };
+        if !opts.hidden && is_hidden(&entry.file_name()) {
+            continue;
+        }
+
+        match _process_path(entry.path(), opts, depth, ancestors) {
+            Ok(k) => v.push(k),
+            Err(err) => report(err),
         }
     }
 
     return Ok(v);
 }
 
-pub struct Cell {
-    pub chr: char,
-    pub col: Color,
+/// reads the contents of a directory, problems with single entries are
+/// reported and the entries skipped
+pub fn read_dir(path: &PathBuf, opts: &ReadOptions, depth: u64) -> Result<Vec<Entry>, EntryError> {
+    let meta = path.metadata().map_err(|source| EntryError::OpenDir {
+        path: path.clone(),
+        source,
+    })?;
+    _read_dir(path, opts, depth, &mut vec![(meta.dev(), meta.ino())])
+}
+
+/// a run of text drawn in a single style
+pub struct Span {
+    pub text: String,
+    pub style: Style,
 }
 
 pub struct Format {
-    pub v: Vec<Cell>,
+    pub spans: Vec<Span>,
     pub childs: Vec<Format>,
 }
 
+/// control characters never reach the terminal, whatever the formatters return
+fn safe_char(chr: char) -> char {
+    if chr.is_control() { '?' } else { chr }
+}
+
 impl From<&str> for Format {
     fn from(value: &str) -> Self {
-        let mut v = Vec::new();
-        for chr in value.chars() {
-            v.push(Cell {
-                chr,
-                col: Color::WHITE,
-            });
-        }
-
-        return Format {
-            v,
+        let mut fmt = Format {
+            spans: Vec::new(),
             childs: Vec::new(),
         };
+        fmt.push(value, Style::default());
+        fmt
     }
 }
 
+/// reads a span table, either `{"text", fg=..., bold=...}` or the older cell
+/// form `{chr="c", col=...}`. `col` may be a color or a style, the style keys
+/// can also be set on the table itself
+fn span_from_table(t: &Table) -> mlua::Result<(String, Style)> {
+    let text = match t.get::<Option<String>>(1)? {
+        Some(text) => text,
+        None => t.get::<String>("chr")?,
+    };
+    let mut style: Style = t.get::<Option<Style>>("col")?.unwrap_or_default();
+    style.merge_table(t)?;
+    Ok((text, style))
+}
+
+/// a nested table is a whole format, eg: `{ "a ", {"b", fg="red"} }`, when it
+/// has more than one element or holds tables. Otherwise it is a span
+fn is_format(t: &Table) -> mlua::Result<bool> {
+    if t.raw_len() > 1 {
+        return Ok(true);
+    }
+    for val in t.sequence_values::<mlua::Value>() {
+        if val?.is_table() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// a string, or a list of spans, strings and other such lists
 impl FromLua for Format {
-    fn from_lua(value: mlua::Value, _: &mlua::Lua) -> mlua::Result<Self> {
+    fn from_lua(value: mlua::Value, lua: &mlua::Lua) -> mlua::Result<Self> {
         match value {
-            mlua::Value::String(s) => {
-                return Ok(Format::from(s.to_string_lossy().as_str()));
-            }
+            mlua::Value::String(s) => Ok(Format::from(s.to_string_lossy().as_str())),
             mlua::Value::Table(t) => {
-                let mut v = Vec::new();
-                for kv in t.pairs() {
-                    let (_, val): (usize, Table) = kv?;
-
-                    let chr: String = val.get("chr")?;
-                    let chr = chr.chars().into_iter().next().ok_or(anyhow!("no char?"))?;
-                    let col: Color = val.get("col")?;
-                    v.push(Cell { chr, col })
+                let mut fmt = Format::from("");
+                for val in t.sequence_values::<mlua::Value>() {
+                    match val? {
+                        mlua::Value::String(s) => fmt.push(&s.to_string_lossy(), Style::default()),
+                        // a whole format, eg: what another formatter returned
+                        mlua::Value::Table(t) if is_format(&t)? => {
+                            for span in lua.unpack::<Format>(mlua::Value::Table(t))?.spans {
+                                fmt.push(&span.text, span.style);
+                            }
+                        }
+                        mlua::Value::Table(t) => {
+                            let (text, style) = span_from_table(&t)?;
+                            fmt.push(&text, style);
+                        }
+                        val => {
+                            return Err(mlua::Error::FromLuaConversionError {
+                                from: val.type_name(),
+                                to: "Span".to_string(),
+                                message: Some("expected a string or a span table".to_string()),
+                            });
+                        }
+                    }
                 }
-
-                return Ok(Format {
-                    v,
-                    childs: Vec::new(),
-                });
+                Ok(fmt)
             }
-            _ => {
-                return Err(mlua::Error::FromLuaConversionError {
-                    from: "any",
-                    to: "Format".to_string(),
-                    message: Some("idk man".to_string()),
-                });
+            val => Err(mlua::Error::FromLuaConversionError {
+                from: val.type_name(),
+                to: "Format".to_string(),
+                message: Some("expected a string or a list of spans".to_string()),
+            }),
+        }
+    }
+}
+
+/// a list of `{"text", col=style}` spans, it reads back as the same format
+impl IntoLua for Format {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        let spans = lua.create_table()?;
+        for span in self.spans {
+            let t = lua.create_table()?;
+            t.push(span.text)?;
+            t.set("col", span.style)?;
+            spans.push(t)?;
+        }
+        spans.into_lua(lua)
+    }
+}
+
+/// the lua function an entry is formatted with, the rules first and then
+/// what its type has registered
+pub fn formatter(entry: &Entry) -> Function {
+    match_rule(entry).unwrap_or_else(|| match entry.ty {
+        EntryType::File => format_file(entry),
+        EntryType::Dir => format_dir(&entry.path),
+        EntryType::SymLink => format_link(&entry.path),
+        EntryType::Executable => format_exec(entry),
+        EntryType::Fifo => format_generic(&FileType::GenericFifo),
+        EntryType::Socket => format_generic(&FileType::GenericSocket),
+        EntryType::BlockDevice => format_generic(&FileType::GenericBlockDevice),
+        EntryType::CharDevice => format_generic(&FileType::GenericCharDevice),
+    })
+}
+
+impl Format {
+    /// formats only the entry itself, the childs are left empty. `depth` is
+    /// how far below the listed directory the entry is
+    pub fn from_entry(entry: &Entry, depth: usize) -> Result<Self> {
+        let formatter = formatter(entry);
+
+        let meta = EntryMeta::new(entry, depth);
+        let mut fmt =
+            formatter.call::<Format>((entry.display_name(), entry.path.as_path(), 0, meta))?;
+        // dangling links are always drawn in red, whatever the formatter did
+        if entry.broken {
+            let col = *BROKEN_LINK_COLOR.lock().unwrap();
+            for span in &mut fmt.spans {
+                span.style.fg = col;
             }
         }
+        Ok(fmt)
+    }
+
+    /// formats the entry and everything below it
+    pub fn from_tree(entry: Entry, depth: usize) -> Result<Self> {
+        let mut fmt = Format::from_entry(&entry, depth)?;
+        for child in entry.childs {
+            fmt.childs.push(Format::from_tree(child, depth + 1)?);
+        }
+        Ok(fmt)
+    }
+}
+
+impl TryFrom<&Entry> for Format {
+    type Error = anyhow::Error;
+
+    fn try_from(entry: &Entry) -> Result<Self> {
+        Format::from_entry(entry, 0)
     }
 }
 
@@ -249,73 +882,141 @@ impl TryFrom<Entry> for Format {
     type Error = anyhow::Error;
 
     fn try_from(entry: Entry) -> Result<Self> {
-        let mut childs = Vec::new();
-        let formatter = if let EntryType::File = entry.ty {
-            format_file(&entry.path)
-        } else if let EntryType::Dir = entry.ty {
-            for child in entry.childs {
-                childs.push(Format::try_from(child)?);
-            }
-            format_dir(&entry.path)
-        } else {
-            format_link(&entry.path)
-        };
-
-        let mut fmt = formatter.call::<Format>((entry.name, entry.path, 0))?;
-        fmt.childs = childs;
-        return Ok(fmt);
+        Format::from_tree(entry, 0)
     }
 }
 
-fn rec_format_format(
-    fmt: &Format,
-    depth: u64,
+pub struct TreeStyle {
+    branch: &'static str,
+    last: &'static str,
+    pipe: &'static str,
+    blank: &'static str,
+}
+
+pub const UNICODE_TREE: TreeStyle = TreeStyle {
+    branch: "├── ",
+    last: "└── ",
+    pipe: "│   ",
+    blank: "    ",
+};
+
+pub const ASCII_TREE: TreeStyle = TreeStyle {
+    branch: "|-- ",
+    last: "`-- ",
+    pipe: "|   ",
+    blank: "    ",
+};
+
+/// displays a list of sibling formats and their childs with `tree` like guides
+pub struct Tree<'a> {
+    pub fmts: &'a [Format],
+    pub style: &'a TreeStyle,
+}
+
+fn rec_format_tree(
+    fmts: &[Format],
+    style: &TreeStyle,
+    prefix: &mut String,
     f: &mut std::fmt::Formatter<'_>,
 ) -> std::fmt::Result {
-    writeln!(f, "{}{}", "\t".repeat(depth as usize), fmt)?;
-    for child in &fmt.childs {
-        rec_format_format(&child, depth + 1, f)?;
+    for (i, fmt) in fmts.iter().enumerate() {
+        let last = i + 1 == fmts.len();
+        let connector = if last { style.last } else { style.branch };
+        writeln!(f, "{prefix}{connector}{fmt}")?;
+
+        let len = prefix.len();
+        prefix.push_str(if last { style.blank } else { style.pipe });
+        rec_format_tree(&fmt.childs, style, prefix, f)?;
+        prefix.truncate(len);
+    }
+    Ok(())
+}
+
+impl Display for Tree<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        rec_format_tree(self.fmts, self.style, &mut String::new(), f)
     }
-    return Ok(());
 }
 
 impl Display for Color {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::BLACK => write!(f, "\x1b[30m")?,
-            Self::RED => write!(f, "\x1b[31m")?,
-            Self::GREEN => write!(f, "\x1b[32m")?,
-            Self::YELLOW => write!(f, "\x1b[33m")?,
-            Self::BLUE => write!(f, "\x1b[34m")?,
-            Self::MAGENTA => write!(f, "\x1b[35m")?,
-            Self::CYAN => write!(f, "\x1b[36m")?,
-            Self::WHITE => write!(f, "\x1b[37m")?,
-            Self::RGB(r, g, b) => write!(f, "\x1b[38;2;{r};{g};{b}m")?,
+        if !use_color() {
+            return Ok(());
         }
 
-        Ok(())
+        write!(f, "\x1b[{}m", self.sgr(false))
     }
 }
 
-impl Display for Format {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut pc = Color::WHITE;
-        if f.alternate() {
-            return rec_format_format(self, 0, f);
+/// columns taken by a single grapheme cluster
+pub fn grapheme_width(g: &str) -> usize {
+    UnicodeWidthStr::width(g)
+}
+
+impl Format {
+    /// appends text, merged into the last span when the style is the same
+    pub fn push(&mut self, text: &str, style: Style) {
+        let text: String = text.chars().map(safe_char).collect();
+        if text.is_empty() {
+            return;
+        }
+        match self.spans.last_mut() {
+            Some(last) if last.style == style => last.text.push_str(&text),
+            _ => self.spans.push(Span { text, style }),
         }
+    }
+
+    /// the grapheme clusters in order, each with the style it's drawn in
+    pub fn graphemes(&self) -> impl Iterator<Item = (&str, Style)> {
+        self.spans
+            .iter()
+            .flat_map(|span| span.text.graphemes(true).map(|g| (g, span.style)))
+    }
+
+    /// columns taken on the terminal
+    pub fn width(&self) -> usize {
+        self.graphemes().map(|(g, _)| grapheme_width(g)).sum()
+    }
 
-        for cell in &self.v {
-            if cell.col != pc {
-                pc = cell.col;
-                write!(f, "{pc}")?;
+    /// the characters without any styling
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|span| span.text.as_str()).collect()
+    }
+
+    fn write_spans(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !use_color() {
+            return write!(f, "{}", self.text());
+        }
+
+        let mut ps = Style::default();
+        for span in &self.spans {
+            if span.style != ps {
+                ps = span.style;
+                write!(f, "{}", ps.escape())?;
             }
-            write!(f, "{}", cell.chr)?;
+            write!(f, "{}", span.text)?;
         }
 
-        if pc != Color::WHITE {
-            write!(f, "\x1b[0m")?;
+        if ps != Style::default() {
+            write!(f, "{}", reset())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_spans(f)?;
+        if f.alternate() {
+            writeln!(f)?;
+            let tree = Tree {
+                fmts: &self.childs,
+                style: &UNICODE_TREE,
+            };
+            write!(f, "{tree}")?;
         }
 
-        return Ok(());
+        Ok(())
     }
 }
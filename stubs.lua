@@ -1,6 +1,145 @@
----@param t table
+---@class EntryMeta
+---@field type "file"|"dir"|"symlink"|"executable"|"fifo"|"socket"|"block"|"char"
+---@field size integer
+---@field mode string `ls -l` style, eg: `drwxr-xr-x`
+---@field perm integer permission bits
+---@field nlink integer
+---@field uid integer
+---@field gid integer
+---@field user string
+---@field group string
+---@field mtime integer seconds since the epoch
+---@field ctime integer
+---@field atime integer
+---@field target string? where a symlink points to
+---@field broken boolean symlink whose target does not exist
+---@field extension string?
+---@field hidden boolean
+---@field depth integer how far below the listed directory
+---@field children integer? number of entries of a directory
+---@field mime string? found from the first bytes of regular files, eg: `image/png`
+---@field format_target fun(self: EntryMeta, name: string?): table|string|nil what the formatter of a symlink's target returns, nil if it dangles
+
+---@alias Formatter fun(name: string, path: string, tick: integer, meta: EntryMeta): table|string
+
+---@class DirEntry
+---@field name string
+---@field path string
+---@field type string?
+
+---@class Stat
+---@field type string
+---@field size integer
+---@field mode string
+---@field perm integer
+---@field nlink integer
+---@field uid integer
+---@field gid integer
+---@field user string
+---@field group string
+---@field mtime integer
+---@field ctime integer
+---@field atime integer
+---@field dev integer
+---@field ino integer
+
+fs = {
+	---@type fun(path: string): DirEntry[]
+	read_dir = nil,
+	---@type fun(path: string): Stat
+	stat = nil,
+	---@type fun(path: string): Stat doesn't follow symlinks
+	lstat = nil,
+	---@type fun(path: string): boolean
+	exists = nil,
+	---@type fun(path: string): boolean
+	is_dir = nil,
+	---@type fun(path: string, max: integer?): string the first `max` bytes, never more than a megabyte
+	read_file = nil,
+	---@type fun(pattern: string): string[] `*`, `?`, `[...]` and `**` for any number of directories
+	glob = nil,
+	---@type fun(path: string): string
+	realpath = nil,
+	---@type fun(path: string): string
+	readlink = nil,
+	---@type fun(marker: string, start: string?): string? path of the marker in start or its parents
+	find_up = nil,
+}
+
+---@class Rule
+---@field path string? full path, wildcards allowed. the most specific kind of rule
+---@field name string? exact file name
+---@field glob string? `*`, `?` and `[...]` on the file name
+---@field regex string? regular expression on the file name, the least specific
+---@field ignore_case boolean?
+---@field type string? only entries of this type, see `EntryMeta.type`
+---@field format Formatter
+
+---@class Formats
+---@field file table<integer|string, Formatter> the default at [1], then by extension, longest first (`tar.gz` before `gz`)
+---@field dirs table<integer|string, Formatter> the default at [1], then by directory name
+---@field links (Formatter|table<integer, Formatter>)? `meta.target`, `meta.broken` and `meta:format_target()` describe the target
+---@field exec Formatter?
+---@field fifo Formatter?
+---@field socket Formatter?
+---@field block Formatter?
+---@field char Formatter?
+---@field mime table<string, Formatter>? by the type found from the content of files without a known extension, `image/png` then `image/*`. files are only read when this is given
+---@field rules Rule[]? matched before the tables above, first by kind then in order
+---@field ignore_case boolean? extensions match whatever their case
+
+---@param t Formats
 load_formats = function(t) end
 
+---the lines shown in the explorer's preview pane: a string is split on newlines, a table
+---holds one string or format per line. nil falls back to the builtin preview.
+---previewers run in a lua state of their own, the config is run a second time for it and
+---only `load_previews` counts there. a slow command never holds up the listing
+---@alias Previewer fun(path: string, meta: EntryMeta, width: integer, height: integer): table|string|nil
+
+---@class Previews
+---@field file table<integer|string, Previewer>? the default at [1], then by extension
+---@field dirs table<integer|string, Previewer>? the default at [1], then by directory name
+---@field mime table<string, Previewer>?
+---@field exec Previewer?
+---@field links Previewer? only dangling links, the others are previewed as their target
+---@field fifo Previewer?
+---@field socket Previewer?
+---@field block Previewer?
+---@field char Previewer?
+
+---@param t Previews
+load_previews = function(t) end
+
+---@class Layout
+---@field columns integer[]? relative widths: the listing alone, with the preview, or the parent directory, the listing and the preview. narrow terminals drop the parent then the preview
+
+---@param t Layout
+load_layout = function(t) end
+
+---@class RunOptions
+---@field cmd string program to start, there is no shell involved
+---@field args string[]?
+---@field cwd string?
+---@field env table<string, string>? added to the inherited environment
+---@field stdin string?
+---@field timeout_ms integer? the process is killed after it
+---@field cache boolean? reuse the result of the same call from the same line, true by default
+
+---@class RunResult
+---@field stdout string
+---@field stderr string
+---@field code integer? nil when killed by a signal
+---@field signal integer?
+---@field timed_out boolean
+---@field ok boolean exited with 0
+
+process = {
+	---@type fun(opts: RunOptions): RunResult
+	run = nil,
+}
+
+---@deprecated use process.run
 ---@param s string
 ---@return (string, string, number)
 bash = function(s)